@@ -0,0 +1,234 @@
+use crate::{AgentRequest, ClientMessage, ClientMessageDetail, ServerMessage};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+/// the version of the wire format, bumped when a change can't be decoded by older peers.
+///
+/// version 1 is the raw bincode of the messages, without an [`Envelope`]
+pub const PROTOCOL_VERSION: u16 = 2;
+
+/// the requests known to this build, the features of a connection are the ones known to both sides
+pub const FEATURES: &[&str] = &[
+    "run_command",
+    "units",
+    "fetch_file",
+    "report_interval",
+    "rotate_token",
+    "self_update",
+];
+
+/// prefix of the enveloped frames, the raw frames of version 1 start with an option or enum tag
+const MAGIC: &[u8] = b"MA";
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Envelope {
+    pub version: u16,
+    /// the name of the message, readable even if the payload is not.
+    /// requests are named after their feature
+    pub kind: String,
+    /// set on requests and responses, so an unknown request can still be answered
+    pub request_id: Option<u64>,
+    pub payload: Vec<u8>,
+}
+
+pub trait Message: Serialize + DeserializeOwned {
+    fn kind(&self) -> &'static str;
+
+    fn request_id(&self) -> Option<u64> {
+        None
+    }
+}
+
+#[derive(Debug)]
+pub enum Frame<T> {
+    /// an enveloped message and the protocol version of its sender
+    Message { version: u16, message: T },
+    /// a raw message of a version 1 peer
+    Legacy(T),
+    /// the envelope is valid but its payload is unknown to this build, e.g. a newer message type
+    Unknown(Envelope),
+}
+
+/// wrap the message in an envelope of the current [`PROTOCOL_VERSION`]
+pub fn encode<T: Message>(message: &T) -> bincode::Result<Vec<u8>> {
+    let envelope = Envelope {
+        version: PROTOCOL_VERSION,
+        kind: message.kind().into(),
+        request_id: message.request_id(),
+        payload: bincode::serialize(message)?,
+    };
+    let mut data = MAGIC.to_vec();
+    bincode::serialize_into(&mut data, &envelope)?;
+    Ok(data)
+}
+
+/// encode the message for a peer speaking `version`, older peers get the raw message
+pub fn encode_for<T: Message>(message: &T, version: u16) -> bincode::Result<Vec<u8>> {
+    if version < 2 {
+        return bincode::serialize(message);
+    }
+    encode(message)
+}
+
+pub fn decode<T: Message>(data: &[u8]) -> bincode::Result<Frame<T>> {
+    let Some(data) = data.strip_prefix(MAGIC) else {
+        return bincode::deserialize(data).map(Frame::Legacy);
+    };
+    let envelope = bincode::deserialize::<Envelope>(data)?;
+    Ok(match bincode::deserialize(&envelope.payload) {
+        Ok(message) => Frame::Message {
+            version: envelope.version,
+            message,
+        },
+        Err(_) => Frame::Unknown(envelope),
+    })
+}
+
+/// the features of `offered` that are known to this build
+pub fn negotiate(offered: &[String]) -> Vec<String> {
+    offered
+        .iter()
+        .filter(|f| FEATURES.contains(&f.as_str()))
+        .cloned()
+        .collect()
+}
+
+impl AgentRequest {
+    /// the feature the agent needs to handle the request, see [`FEATURES`]
+    pub fn feature(&self) -> &'static str {
+        match self {
+            AgentRequest::RunCommand { .. } => "run_command",
+            AgentRequest::ListUnits | AgentRequest::ManageUnit { .. } => "units",
+            AgentRequest::FetchFile { .. } => "fetch_file",
+            AgentRequest::SetReportInterval { .. } => "report_interval",
+            AgentRequest::RotateToken { .. } => "rotate_token",
+            AgentRequest::BeginUpdate { .. }
+            | AgentRequest::UpdateChunk { .. }
+            | AgentRequest::ApplyUpdate => "self_update",
+        }
+    }
+}
+
+impl Message for ClientMessage {
+    fn kind(&self) -> &'static str {
+        match self.message {
+            ClientMessageDetail::Ping => "ping",
+            ClientMessageDetail::AgentInfo { .. } => "agent_info",
+            ClientMessageDetail::UpdateMetric { .. } => "update_metric",
+            ClientMessageDetail::Response { .. } => "response",
+            ClientMessageDetail::Hello { .. } => "hello",
+        }
+    }
+
+    fn request_id(&self) -> Option<u64> {
+        match self.message {
+            ClientMessageDetail::Response { id, .. } => Some(id),
+            _ => None,
+        }
+    }
+}
+
+impl Message for ServerMessage {
+    fn kind(&self) -> &'static str {
+        match self {
+            ServerMessage::Ping => "ping",
+            ServerMessage::Request { request, .. } => request.feature(),
+            ServerMessage::Welcome { .. } => "welcome",
+        }
+    }
+
+    fn request_id(&self) -> Option<u64> {
+        match self {
+            ServerMessage::Request { id, .. } => Some(*id),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request() -> ServerMessage {
+        ServerMessage::Request {
+            id: 7,
+            request: AgentRequest::RunCommand {
+                command: "uptime".into(),
+                timeout_secs: 5,
+            },
+        }
+    }
+
+    #[test]
+    fn round_trips_an_envelope() {
+        let data = encode(&request()).unwrap();
+        assert!(data.starts_with(MAGIC));
+
+        let frame = decode::<ServerMessage>(&data).unwrap();
+        let Frame::Message { version, message } = frame else {
+            panic!("expected a message, got {frame:?}");
+        };
+        assert_eq!(version, PROTOCOL_VERSION);
+        let ServerMessage::Request { id, request } = message else {
+            panic!("expected a request, got {message:?}");
+        };
+        assert_eq!(id, 7);
+        assert!(matches!(
+            request,
+            AgentRequest::RunCommand { command, timeout_secs: 5 } if command == "uptime"
+        ));
+    }
+
+    #[test]
+    fn decodes_a_raw_frame_as_legacy() {
+        let message = ClientMessage {
+            token: Some("token".into()),
+            message: ClientMessageDetail::Ping,
+        };
+        let data = bincode::serialize(&message).unwrap();
+
+        let frame = decode::<ClientMessage>(&data).unwrap();
+        let Frame::Legacy(message) = frame else {
+            panic!("expected a legacy frame, got {frame:?}");
+        };
+        assert_eq!(message.token.as_deref(), Some("token"));
+        assert!(matches!(message.message, ClientMessageDetail::Ping));
+    }
+
+    #[test]
+    fn keeps_the_request_id_of_an_unknown_message() {
+        let envelope = Envelope {
+            version: PROTOCOL_VERSION + 1,
+            kind: "from_the_future".into(),
+            request_id: Some(42),
+            // a variant index this build doesn't know
+            payload: bincode::serialize(&99u32).unwrap(),
+        };
+        let mut data = MAGIC.to_vec();
+        bincode::serialize_into(&mut data, &envelope).unwrap();
+
+        let frame = decode::<ServerMessage>(&data).unwrap();
+        let Frame::Unknown(envelope) = frame else {
+            panic!("expected an unknown frame, got {frame:?}");
+        };
+        assert_eq!(envelope.kind, "from_the_future");
+        assert_eq!(envelope.request_id, Some(42));
+    }
+
+    #[test]
+    fn encodes_plain_bincode_for_version_1() {
+        let data = encode_for(&request(), 1).unwrap();
+        assert!(!data.starts_with(MAGIC));
+        assert_eq!(data, bincode::serialize(&request()).unwrap());
+        assert!(matches!(
+            decode::<ServerMessage>(&data).unwrap(),
+            Frame::Legacy(ServerMessage::Request { id: 7, .. })
+        ));
+    }
+
+    #[test]
+    fn negotiates_the_known_features() {
+        let offered = vec!["units".to_string(), "from_the_future".to_string()];
+        assert_eq!(negotiate(&offered), vec!["units".to_string()]);
+    }
+}
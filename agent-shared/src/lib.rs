@@ -1,26 +1,105 @@
 use machine_info::{SystemInfo, SystemStatus};
 use serde::{Deserialize, Serialize};
 
+mod envelope;
+pub use envelope::*;
+
 #[derive(Debug)]
 pub enum Signal {
     Init,
+    /// drop the connections that did not complete the handshake in time
+    SweepHandshakes,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ClientMessage {
+    /// only set by the agents that predate the [`ClientMessageDetail::Hello`] handshake,
+    /// the connection is trusted once the handshake succeeded
     pub token: Option<String>,
     pub message: ClientMessageDetail,
 }
 
+/// new variants must be added at the end, bincode encodes the variant index.
+/// peers that don't know a variant skip it, see [`Frame::Unknown`]
 #[derive(Serialize, Deserialize, Debug)]
 pub enum ClientMessageDetail {
     Ping,
     UpdateMetric { metric: AddServerMetric },
+    /// the answer to a [`ServerMessage::Request`] with the same id
+    Response { id: u64, response: AgentResponse },
+    /// the first message on every connection, the other messages are only accepted after it
+    Hello {
+        token: String,
+        agent_version: u16,
+        capabilities: Vec<String>,
+    },
+    /// sent once after connecting by the agents that predate the handshake
+    AgentInfo { version: u16 },
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub enum ServerMessage {
     Ping,
+    /// the agent must answer with a [`ClientMessageDetail::Response`] carrying the same id
+    Request { id: u64, request: AgentRequest },
+    /// the answer to a [`ClientMessageDetail::Hello`], `features` are the ones both sides know
+    Welcome {
+        protocol_version: u16,
+        features: Vec<String>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum AgentRequest {
+    /// runs the command with `sh -c`
+    RunCommand { command: String, timeout_secs: u64 },
+    /// every service unit with its state
+    ListUnits,
+    ManageUnit { unit: String, action: UnitAction },
+    FetchFile { path: String },
+    /// how often the metrics are reported
+    SetReportInterval { secs: u64 },
+    /// replace the token of the agent, the agent must persist it before answering
+    RotateToken { token: String },
+    /// start receiving a new binary of the agent, `sha256` is the hex digest of the whole binary
+    BeginUpdate { size: u64, sha256: String },
+    /// a part of the new binary, `offset` must be the number of bytes received so far
+    UpdateChunk { offset: u64, data: Vec<u8> },
+    /// verify the received binary, replace the agent with it and restart.
+    /// the previous binary is restored if the new one doesn't reconnect in time
+    ApplyUpdate,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum AgentResponse {
+    Done,
+    CommandOutput {
+        stdout: String,
+        stderr: String,
+        exit_code: Option<i32>,
+        timed_out: bool,
+    },
+    File { content: Vec<u8> },
+    Units { units: Vec<UnitState> },
+    Error { message: String },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum UnitAction {
+    Start,
+    Stop,
+    Restart,
+    Enable,
+    Disable,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UnitState {
+    pub name: String,
+    /// unit file state, e.g. `enabled`, `disabled`, `static`
+    pub state: String,
+    pub active: bool,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -28,3 +107,56 @@ pub struct AddServerMetric {
     pub system_info: SystemInfo,
     pub system_status: Option<SystemStatus>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use machine_info::Machine;
+
+    /// the messages as the agents of the first release encode them
+    mod baseline {
+        use crate::AddServerMetric;
+        use serde::Serialize;
+
+        #[derive(Serialize)]
+        pub struct ClientMessage {
+            pub token: Option<String>,
+            pub message: ClientMessageDetail,
+        }
+
+        #[derive(Serialize)]
+        pub enum ClientMessageDetail {
+            #[allow(dead_code)]
+            Ping,
+            UpdateMetric {
+                metric: AddServerMetric,
+            },
+        }
+    }
+
+    #[test]
+    fn decodes_the_metrics_of_baseline_agents() {
+        let system_info = Machine::new().system_info();
+        let memory = system_info.memory;
+        let message = baseline::ClientMessage {
+            token: Some("token".into()),
+            message: baseline::ClientMessageDetail::UpdateMetric {
+                metric: AddServerMetric {
+                    system_info,
+                    system_status: None,
+                },
+            },
+        };
+        let data = bincode::serialize(&message).unwrap();
+
+        let frame = decode::<ClientMessage>(&data).unwrap();
+        let Frame::Legacy(message) = frame else {
+            panic!("expected a legacy frame, got {frame:?}");
+        };
+        assert_eq!(message.token.as_deref(), Some("token"));
+        let ClientMessageDetail::UpdateMetric { metric } = message.message else {
+            panic!("expected a metric, got {:?}", message.message);
+        };
+        assert_eq!(metric.system_info.memory, memory);
+    }
+}
@@ -11,4 +11,7 @@ pub struct ServerMetric {
 pub struct Config {
     pub api_host:String,
     pub auth_token:String,
+    /// connect without tls when no certificate was issued to the agent, must be set by hand
+    #[serde(default)]
+    pub allow_plaintext: bool,
 }
@@ -0,0 +1,119 @@
+use crate::models::Config;
+use crate::{systemd_manager, updater, CONFIG_FILE_PATH};
+use agent_shared::{AgentRequest, AgentResponse};
+use std::io::Read;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::RwLock;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+/// files bigger than this are not sent back to the server
+const MAX_FILE_SIZE: u64 = 4 * 1024 * 1024;
+const MIN_REPORT_INTERVAL_SECS: u64 = 1;
+
+pub fn handle(
+    request: AgentRequest,
+    report_interval: &AtomicU64,
+    token: &RwLock<String>,
+) -> AgentResponse {
+    let result = match request {
+        AgentRequest::RunCommand {
+            command,
+            timeout_secs,
+        } => run_command(&command, Duration::from_secs(timeout_secs)),
+        AgentRequest::ListUnits => {
+            systemd_manager::list_units().map(|units| AgentResponse::Units { units })
+        }
+        AgentRequest::ManageUnit { unit, action } => {
+            systemd_manager::manage_unit(&unit, action).map(|_| AgentResponse::Done)
+        }
+        AgentRequest::FetchFile { path } => fetch_file(&path),
+        AgentRequest::SetReportInterval { secs } => {
+            report_interval.store(secs.max(MIN_REPORT_INTERVAL_SECS), Ordering::Relaxed);
+            Ok(AgentResponse::Done)
+        }
+        AgentRequest::RotateToken { token: new_token } => {
+            rotate_token(new_token, token).map(|_| AgentResponse::Done)
+        }
+        AgentRequest::BeginUpdate { size, sha256 } => {
+            updater::begin(size, sha256).map(|_| AgentResponse::Done)
+        }
+        AgentRequest::UpdateChunk { offset, data } => {
+            updater::write_chunk(offset, &data).map(|_| AgentResponse::Done)
+        }
+        AgentRequest::ApplyUpdate => updater::apply().map(|_| AgentResponse::Done),
+    };
+
+    result.unwrap_or_else(|e| AgentResponse::Error {
+        message: e.to_string(),
+    })
+}
+
+fn run_command(command: &str, timeout: Duration) -> eyre::Result<AgentResponse> {
+    let mut child = Command::new("sh")
+        .args(["-c", command])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // read both pipes in the background, otherwise the child blocks once a pipe is full
+    let mut stdout = child.stdout.take().unwrap();
+    let mut stderr = child.stderr.take().unwrap();
+    let stdout_reader = std::thread::spawn(move || {
+        let mut buf = vec![];
+        let _ = stdout.read_to_end(&mut buf);
+        buf
+    });
+    let stderr_reader = std::thread::spawn(move || {
+        let mut buf = vec![];
+        let _ = stderr.read_to_end(&mut buf);
+        buf
+    });
+
+    let instant = Instant::now();
+    let mut timed_out = false;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break Some(status);
+        }
+        if instant.elapsed() >= timeout {
+            timed_out = true;
+            let _ = child.kill();
+            let _ = child.wait();
+            break None;
+        }
+        sleep(Duration::from_millis(50));
+    };
+
+    let stdout = stdout_reader.join().unwrap_or_default();
+    let stderr = stderr_reader.join().unwrap_or_default();
+    Ok(AgentResponse::CommandOutput {
+        stdout: String::from_utf8_lossy(&stdout).to_string(),
+        stderr: String::from_utf8_lossy(&stderr).to_string(),
+        exit_code: status.and_then(|s| s.code()),
+        timed_out,
+    })
+}
+
+/// persist the token first, so the agent can't lose it on a restart
+fn rotate_token(new_token: String, token: &RwLock<String>) -> eyre::Result<()> {
+    let mut config = serde_json::from_str::<Config>(&std::fs::read_to_string(CONFIG_FILE_PATH)?)?;
+    config.auth_token = new_token.clone();
+    std::fs::write(CONFIG_FILE_PATH, serde_json::to_string(&config)?.as_bytes())?;
+    *token.write().unwrap() = new_token;
+    Ok(())
+}
+
+fn fetch_file(path: &str) -> eyre::Result<AgentResponse> {
+    let size = std::fs::metadata(path)?.len();
+    if size > MAX_FILE_SIZE {
+        return Err(eyre::eyre!(
+            "{path} is too big ({size} bytes), the limit is {MAX_FILE_SIZE} bytes"
+        ));
+    }
+    Ok(AgentResponse::File {
+        content: std::fs::read(path)?,
+    })
+}
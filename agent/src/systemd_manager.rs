@@ -1,6 +1,8 @@
+use agent_shared::{UnitAction, UnitState};
+use eyre::eyre;
 use systemctl::SystemCtl;
 
-const AGENT_UNIT_NAME: &str = "managers_agent";
+pub const AGENT_UNIT_NAME: &str = "managers_agent";
 const AGENT_UNIT_PATH: &str = "/etc/systemd/system";
 
 const UNIT_TEMPLATE: &str = r#"
@@ -41,3 +43,34 @@ pub fn init_systemd() -> eyre::Result<()> {
 
     Ok(())
 }
+
+pub fn list_units() -> eyre::Result<Vec<UnitState>> {
+    let systemd = SystemCtl::default();
+    let units = systemd.list_units_full(Some("service"), None, None)?;
+    Ok(units
+        .into_iter()
+        .map(|u| UnitState {
+            active: systemd.is_active(&u.unit_file).unwrap_or_default(),
+            name: u.unit_file,
+            state: u.state,
+        })
+        .collect())
+}
+
+pub fn manage_unit(unit: &str, action: UnitAction) -> eyre::Result<()> {
+    let systemd = SystemCtl::default();
+    if !systemd.exists(unit)? {
+        return Err(eyre!("unit {unit} does not exist"));
+    }
+    let status = match action {
+        UnitAction::Start => systemd.start(unit)?,
+        UnitAction::Stop => systemd.stop(unit)?,
+        UnitAction::Restart => systemd.restart(unit)?,
+        UnitAction::Enable => systemd.enable(unit)?,
+        UnitAction::Disable => systemd.disable(unit)?,
+    };
+    if !status.success() {
+        return Err(eyre!("failed to {action:?} {unit}: {status}"));
+    }
+    Ok(())
+}
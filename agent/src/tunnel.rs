@@ -0,0 +1,81 @@
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+use tokio_rustls::TlsConnector;
+
+const CA_CERT_PATH: &str = "/usr/share/managers_agent/ca.cert.pem";
+const CERT_PATH: &str = "/usr/share/managers_agent/agent.cert.pem";
+const KEY_PATH: &str = "/usr/share/managers_agent/agent.key.pem";
+/// the name in the certificate of the server, the agent connects by ip
+const SERVER_NAME: &str = "managers-server";
+
+/// whether the server issued a certificate to this agent (agents deployed before the tls support don't have one)
+pub fn is_configured() -> bool {
+    [CA_CERT_PATH, CERT_PATH, KEY_PATH]
+        .iter()
+        .all(|p| Path::new(p).exists())
+}
+
+/// message-io has no tls support, so the agent connects to a local forwarder
+/// which wraps every connection in (mutual) tls to `api_host`.
+///
+/// returns the local address of the forwarder
+pub fn start(api_host: String) -> eyre::Result<SocketAddr> {
+    let connector = TlsConnector::from(Arc::new(client_config()?));
+    let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+    listener.set_nonblocking(true)?;
+    let addr = listener.local_addr()?;
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    std::thread::spawn(move || {
+        runtime.block_on(async move {
+            let listener = TcpListener::from_std(listener).unwrap();
+            loop {
+                let Ok((local, _)) = listener.accept().await else {
+                    continue;
+                };
+                let (connector, api_host) = (connector.clone(), api_host.clone());
+                tokio::spawn(async move {
+                    if let Err(e) = forward(local, &api_host, connector).await {
+                        println!("tls tunnel to {api_host} closed: {e}");
+                    }
+                });
+            }
+        })
+    });
+    Ok(addr)
+}
+
+async fn forward(
+    mut local: TcpStream,
+    api_host: &str,
+    connector: TlsConnector,
+) -> eyre::Result<()> {
+    let remote = TcpStream::connect(api_host).await?;
+    let mut remote = connector
+        .connect(ServerName::try_from(SERVER_NAME)?, remote)
+        .await?;
+    tokio::io::copy_bidirectional(&mut local, &mut remote).await?;
+    Ok(())
+}
+
+fn client_config() -> eyre::Result<ClientConfig> {
+    let mut roots = RootCertStore::empty();
+    roots.add(CertificateDer::from_pem_file(CA_CERT_PATH)?)?;
+    let certs = CertificateDer::pem_file_iter(CERT_PATH)?.collect::<Result<Vec<_>, _>>()?;
+    let key = PrivateKeyDer::from_pem_file(KEY_PATH)?;
+
+    Ok(
+        ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()?
+            .with_root_certificates(roots)
+            .with_client_auth_cert(certs, key)?,
+    )
+}
@@ -0,0 +1,157 @@
+use crate::systemd_manager::AGENT_UNIT_NAME;
+use eyre::eyre;
+use sha2::{Digest, Sha256};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::Command;
+use std::sync::Mutex;
+use std::thread::sleep;
+use std::time::Duration;
+
+const AGENT_PATH: &str = "/usr/bin/managers_agent";
+/// next to the agent, so it can be renamed over it atomically
+const STAGED_PATH: &str = "/usr/bin/managers_agent.update";
+const PREVIOUS_PATH: &str = "/usr/bin/managers_agent.previous";
+/// exists while the new binary did not complete a handshake yet
+const PENDING_MARKER_PATH: &str = "/usr/share/managers_agent/update.pending";
+const ROLLBACK_UNIT_NAME: &str = "managers_agent_rollback";
+/// how long the new binary has to reconnect before the previous one is restored
+const ROLLBACK_AFTER_SECS: u64 = 120;
+
+struct Upload {
+    size: u64,
+    sha256: String,
+    received: u64,
+    hasher: Sha256,
+}
+
+static UPLOAD: Mutex<Option<Upload>> = Mutex::new(None);
+
+/// discard any unfinished upload and start a new one
+pub fn begin(size: u64, sha256: String) -> eyre::Result<()> {
+    File::create(STAGED_PATH)?;
+    *UPLOAD.lock().unwrap() = Some(Upload {
+        size,
+        sha256: sha256.to_lowercase(),
+        received: 0,
+        hasher: Sha256::new(),
+    });
+    println!("receiving an update ({size} bytes)");
+    Ok(())
+}
+
+pub fn write_chunk(offset: u64, data: &[u8]) -> eyre::Result<()> {
+    let mut l = UPLOAD.lock().unwrap();
+    let upload = l.as_mut().ok_or(eyre!("no update is in progress"))?;
+    if offset != upload.received {
+        return Err(eyre!(
+            "expected the chunk at {}, got the one at {offset}",
+            upload.received
+        ));
+    }
+    if upload.received + data.len() as u64 > upload.size {
+        return Err(eyre!("the update is bigger than {} bytes", upload.size));
+    }
+    OpenOptions::new()
+        .append(true)
+        .open(STAGED_PATH)?
+        .write_all(data)?;
+    upload.hasher.update(data);
+    upload.received += data.len() as u64;
+    Ok(())
+}
+
+/// verify the received binary, swap it in and restart the agent through its unit.
+///
+/// a timer restores the previous binary unless the new one confirms the update, see [`confirm`]
+pub fn apply() -> eyre::Result<()> {
+    let upload = UPLOAD
+        .lock()
+        .unwrap()
+        .take()
+        .ok_or(eyre!("no update is in progress"))?;
+    if upload.received != upload.size {
+        return Err(eyre!(
+            "the update is incomplete ({}/{} bytes)",
+            upload.received,
+            upload.size
+        ));
+    }
+    let digest = upload
+        .hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect::<String>();
+    if digest != upload.sha256 {
+        let _ = std::fs::remove_file(STAGED_PATH);
+        return Err(eyre!(
+            "the checksum of the update does not match (expected {}, got {digest})",
+            upload.sha256
+        ));
+    }
+
+    std::fs::set_permissions(STAGED_PATH, std::fs::Permissions::from_mode(0o755))?;
+    std::fs::copy(AGENT_PATH, PREVIOUS_PATH)?;
+    schedule_rollback()?;
+    std::fs::write(PENDING_MARKER_PATH, digest.as_bytes())?;
+    if let Err(e) = std::fs::rename(STAGED_PATH, AGENT_PATH) {
+        cancel_rollback();
+        let _ = std::fs::remove_file(PENDING_MARKER_PATH);
+        return Err(e.into());
+    }
+
+    println!("the update is applied, restarting");
+    // give the response some time to reach the server
+    std::thread::spawn(|| {
+        sleep(Duration::from_secs(1));
+        let _ = Command::new("systemctl")
+            .args(["--no-block", "restart", AGENT_UNIT_NAME])
+            .status();
+    });
+    Ok(())
+}
+
+/// called once the agent is connected, keeps the current binary if it was just updated
+pub fn confirm() -> eyre::Result<()> {
+    if !Path::new(PENDING_MARKER_PATH).exists() {
+        return Ok(());
+    }
+    cancel_rollback();
+    std::fs::remove_file(PENDING_MARKER_PATH)?;
+    let _ = std::fs::remove_file(PREVIOUS_PATH);
+    println!("the update is confirmed");
+    Ok(())
+}
+
+/// the rollback runs as its own transient unit, so it survives the restart of the agent
+/// and works even if the new binary can't start at all
+fn schedule_rollback() -> eyre::Result<()> {
+    cancel_rollback();
+    let script = format!(
+        "test -f {PENDING_MARKER_PATH} && mv -f {PREVIOUS_PATH} {AGENT_PATH} && rm -f {PENDING_MARKER_PATH} && systemctl restart {AGENT_UNIT_NAME}"
+    );
+    let status = Command::new("systemd-run")
+        .args([
+            &format!("--on-active={ROLLBACK_AFTER_SECS}"),
+            &format!("--unit={ROLLBACK_UNIT_NAME}"),
+            // unload the unit even if the rollback fails, so the next update can reuse its name
+            "--collect",
+            "sh",
+            "-c",
+            &script,
+        ])
+        .status()?;
+    if !status.success() {
+        return Err(eyre!("failed to schedule the rollback: {status}"));
+    }
+    Ok(())
+}
+
+fn cancel_rollback() {
+    let _ = Command::new("systemctl")
+        .args(["stop", &format!("{ROLLBACK_UNIT_NAME}.timer")])
+        .status();
+}
@@ -1,19 +1,62 @@
+use crate::api::components::agents::models::RunAgentQuery;
+use crate::api::HANDLER_TIMEOUT;
 use crate::libs::api_response::ApiResponse;
 use crate::libs::shared_state::SharedState;
-use axum::extract::{Path, State};
-use axum::routing::get;
-use axum::{debug_handler, Router};
+use crate::libs::{agent_credentials, agent_update, deploy_jobs};
+use crate::middlewares::rbac_mw::authorize_servers;
+use crate::models::user::User;
+use agent_shared::AgentRequest;
+use axum::extract::{Path, Query, State};
+use axum::middleware::from_fn_with_state;
+use axum::routing::{delete, get, post};
+use axum::{debug_handler, Extension, Json, Router};
+use serde_json::json;
+
+pub mod models;
 
 pub fn routes(state: SharedState) -> Router {
     Router::new()
         .route("/run/{server_id}", get(run_agent))
+        .route("/{server_id}", delete(uninstall_agent))
+        .route("/jobs/{job_id}", get(get_job))
+        .route("/{server_id}/jobs", get(get_jobs))
+        .route("/{server_id}/request", post(send_request))
+        .route("/{server_id}/update", post(update_agent))
+        .route("/{server_id}/credentials", get(get_credentials))
+        .route("/{server_id}/credentials/rotate", post(rotate_credentials))
+        .route("/{server_id}/credentials/revoke", post(revoke_credentials))
+        .route_layer(from_fn_with_state(state.clone(), authorize_servers))
         .with_state(state.clone())
 }
 
+/// queue a deployment of the agent, its progress can be followed with `GET /agents/jobs/{id}`
 #[debug_handler]
 async fn run_agent(
     State(state): State<SharedState>,
     Path(server_id): Path<String>,
+    Query(query): Query<RunAgentQuery>,
+) -> eyre::Result<ApiResponse, ApiResponse> {
+    let server = state
+        .db_driver
+        .get_server_by_id(server_id)
+        .map_err(|e| ApiResponse::internal(&e.to_string()))?
+        .ok_or(ApiResponse::bad_request("server not found"))?;
+
+    let Some(guard) = deploy_jobs::begin(&server.id) else {
+        return Err(ApiResponse::conflict(
+            "a job is already running on the server",
+        ));
+    };
+    let job = deploy_jobs::start(&state, server, query.force, guard)
+        .map_err(|e| ApiResponse::internal(&e.to_string()))?;
+    Ok(ApiResponse::ok("", Some(json!(job))))
+}
+
+/// revoke the token of the agent and remove it from the server, the server itself is kept.
+/// the removal can be followed with `GET /agents/jobs/{id}`
+async fn uninstall_agent(
+    State(state): State<SharedState>,
+    Path(server_id): Path<String>,
 ) -> eyre::Result<ApiResponse, ApiResponse> {
     let server = state
         .db_driver
@@ -21,18 +64,154 @@ async fn run_agent(
         .map_err(|e| ApiResponse::internal(&e.to_string()))?
         .ok_or(ApiResponse::bad_request("server not found"))?;
 
-    //todo check if agent already exist and/or you wanna "force" update it
+    let Some(guard) = deploy_jobs::begin(&server.id) else {
+        return Err(ApiResponse::conflict(
+            "a job is already running on the server",
+        ));
+    };
+    let job = deploy_jobs::start_uninstall(&state, server, false, guard)
+        .map_err(|e| ApiResponse::internal(&e.to_string()))?;
+    Ok(ApiResponse::ok("", Some(json!(job))))
+}
+
+/// the job id is not a server id, so the scope of the user is checked here
+async fn get_job(
+    State(state): State<SharedState>,
+    Extension(user): Extension<User>,
+    Path(job_id): Path<String>,
+) -> eyre::Result<ApiResponse, ApiResponse> {
+    let job = state
+        .db_driver
+        .get_deploy_job(&job_id)
+        .map_err(|e| ApiResponse::internal(&e.to_string()))?
+        .ok_or(ApiResponse::bad_request("job not found"))?;
+    let server = state
+        .db_driver
+        .get_server_by_id(job.server_id.clone())
+        .map_err(|e| ApiResponse::internal(&e.to_string()))?;
+    if user.is_scoped() && !server.is_some_and(|s| user.can_access(&s)) {
+        return Err(ApiResponse::forbidden(
+            "you don't have access to this server",
+        ));
+    }
+    Ok(ApiResponse::ok("", Some(json!(job))))
+}
+
+/// the deployments of the server, newest first
+async fn get_jobs(
+    State(state): State<SharedState>,
+    Path(server_id): Path<String>,
+) -> eyre::Result<ApiResponse, ApiResponse> {
+    state
+        .db_driver
+        .get_deploy_jobs(&server_id)
+        .map(|jobs| ApiResponse::ok("", Some(json!(jobs))))
+        .map_err(|e| ApiResponse::internal(&e.to_string()))
+}
+
+/// push the current agent build to the connected agent, without going through ssh.
+///
+/// the update runs in the background, the agent rolls back if the new version doesn't reconnect.
+/// only one update of a server runs at a time
+async fn update_agent(
+    State(state): State<SharedState>,
+    Path(server_id): Path<String>,
+) -> eyre::Result<ApiResponse, ApiResponse> {
+    if state.agent_hub.session(&server_id).is_none() {
+        return Err(ApiResponse::bad_request(
+            "the agent is not connected, deploy it instead",
+        ));
+    }
+    let Some(guard) = state.agent_hub.begin_update(&server_id) else {
+        return Err(ApiResponse::conflict("the agent is already being updated"));
+    };
 
     tokio::spawn(async move {
-        if let Err(e) = state.agent_service.upload_agent(&server).await {
-            log::error!("{e}");
-            return;
-        };
-
-        if let Err(e) = state.agent_service.init_agent(&server).await {
-            log::error!("{e}");
-        };
+        if let Err(e) = agent_update::update(&state, &server_id).await {
+            log::error!("failed to update the agent of {server_id}: {e}");
+        }
+        drop(guard);
     });
 
     Ok(ApiResponse::ok("", None))
 }
+
+/// forward the request to the connected agent of the server and return its response.
+///
+/// the token of the agent is only replaced through `POST /agents/{server_id}/credentials/rotate`
+/// and a new binary is only pushed through `POST /agents/{server_id}/update`
+async fn send_request(
+    State(state): State<SharedState>,
+    Path(server_id): Path<String>,
+    Json(request): Json<AgentRequest>,
+) -> eyre::Result<ApiResponse, ApiResponse> {
+    if matches!(request, AgentRequest::RotateToken { .. }) {
+        return Err(ApiResponse::bad_request(
+            "rotate the token with POST /agents/{server_id}/credentials/rotate",
+        ));
+    }
+    if matches!(
+        request,
+        AgentRequest::BeginUpdate { .. }
+            | AgentRequest::UpdateChunk { .. }
+            | AgentRequest::ApplyUpdate
+    ) {
+        return Err(ApiResponse::bad_request(
+            "update the agent with POST /agents/{server_id}/update",
+        ));
+    }
+    state
+        .agent_hub
+        .request(&server_id, request, HANDLER_TIMEOUT)
+        .await
+        .map(|r| ApiResponse::ok("", Some(json!(r))))
+        .map_err(|e| ApiResponse::bad_request(e.to_string()))
+}
+
+/// the current credential of the agent and its revoked tokens, the tokens themselves are never returned
+async fn get_credentials(
+    State(state): State<SharedState>,
+    Path(server_id): Path<String>,
+) -> eyre::Result<ApiResponse, ApiResponse> {
+    let credential = state
+        .db_driver
+        .get_agent_credential(&server_id)
+        .map_err(|e| ApiResponse::internal(&e.to_string()))?;
+    let revoked = state
+        .db_driver
+        .get_revoked_agent_tokens(&server_id)
+        .map_err(|e| ApiResponse::internal(&e.to_string()))?;
+    Ok(ApiResponse::ok(
+        "",
+        Some(json!({"current":credential,"revoked":revoked})),
+    ))
+}
+
+/// push a new token to the connected agent and revoke the current one once the agent confirms it
+async fn rotate_credentials(
+    State(state): State<SharedState>,
+    Path(server_id): Path<String>,
+) -> eyre::Result<ApiResponse, ApiResponse> {
+    agent_credentials::rotate(&state, &server_id)
+        .await
+        .map(|_| ApiResponse::ok("", None))
+        .map_err(|e| ApiResponse::bad_request(e.to_string()))
+}
+
+/// revoke every token of the agent and disconnect it, it has to be deployed again to reconnect
+async fn revoke_credentials(
+    State(state): State<SharedState>,
+    Path(server_id): Path<String>,
+) -> eyre::Result<ApiResponse, ApiResponse> {
+    let revoked = state
+        .db_driver
+        .revoke_agent_credential(&server_id)
+        .map_err(|e| ApiResponse::internal(&e.to_string()))?;
+    if !revoked {
+        return Err(ApiResponse::bad_request(
+            "the agent has no credential, deploy it first",
+        ));
+    }
+    state.agent_hub.disconnect(&server_id);
+    Ok(ApiResponse::ok("", None))
+}
@@ -0,0 +1,8 @@
+use serde::Deserialize;
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct RunAgentQuery {
+    /// deploy the agent even if the same build is already running on the server
+    pub force: bool,
+}
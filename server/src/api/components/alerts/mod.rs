@@ -0,0 +1,190 @@
+use crate::api::components::alerts::models::{AddOrUpdateAlertRuleRequest, AlertsQuery};
+use crate::libs::api_response::ApiResponse;
+use crate::libs::shared_state::SharedState;
+use crate::middlewares::rbac_mw::require_operator_for_writes;
+use crate::models::alert::{AlertRule, AlertStatus};
+use crate::models::user::User;
+use axum::extract::{Path, Query, State};
+use axum::middleware::from_fn;
+use axum::routing::{get, put};
+use axum::{Extension, Json, Router};
+use serde_json::json;
+use std::collections::HashSet;
+
+pub mod models;
+
+pub fn routes(state: SharedState) -> Router {
+    Router::new()
+        .route("/", get(get_alerts))
+        .route("/rules", get(get_rules).post(add_rule))
+        .route("/rules/{id}", put(update_rule).delete(delete_rule))
+        .route_layer(from_fn(require_operator_for_writes))
+        .with_state(state.clone())
+}
+
+/// the pending and firing alerts, scoped users only see the alerts of their servers
+async fn get_alerts(
+    State(state): State<SharedState>,
+    Extension(user): Extension<User>,
+    Query(query): Query<AlertsQuery>,
+) -> eyre::Result<ApiResponse, ApiResponse> {
+    let visible = visible_servers(&state, &user)?;
+    let alerts = state
+        .db_driver
+        .all_alerts()
+        .map_err(|e| ApiResponse::internal(&e.to_string()))?
+        .into_iter()
+        .filter(|a| query.all || a.status != AlertStatus::Resolved)
+        .filter(|a| visible.as_ref().is_none_or(|v| v.contains(&a.server_id)))
+        .collect::<Vec<_>>();
+    Ok(ApiResponse::ok("", Some(json!(alerts))))
+}
+
+/// scoped users see the global rules and the rules of their servers
+async fn get_rules(
+    State(state): State<SharedState>,
+    Extension(user): Extension<User>,
+) -> eyre::Result<ApiResponse, ApiResponse> {
+    let visible = visible_servers(&state, &user)?;
+    let rules = state
+        .db_driver
+        .all_alert_rules()
+        .map_err(|e| ApiResponse::internal(&e.to_string()))?
+        .into_iter()
+        .filter(|r| match (&visible, &r.server_id) {
+            (Some(visible), Some(server_id)) => visible.contains(server_id),
+            _ => true,
+        })
+        .collect::<Vec<_>>();
+    Ok(ApiResponse::ok("", Some(json!(rules))))
+}
+
+async fn add_rule(
+    State(state): State<SharedState>,
+    Extension(user): Extension<User>,
+    Json(req): Json<AddOrUpdateAlertRuleRequest>,
+) -> eyre::Result<ApiResponse, ApiResponse> {
+    check_scope(&state, &user, req.server_id.as_ref())?;
+    let id = cuid2::create_id();
+    let rule = to_rule(&state, id.clone(), req)?;
+    state
+        .db_driver
+        .add_alert_rule(rule)
+        .map(|_| ApiResponse::ok("", Some(json!({"id":id}))))
+        .map_err(|e| ApiResponse::internal(&e.to_string()))
+}
+
+async fn update_rule(
+    State(state): State<SharedState>,
+    Extension(user): Extension<User>,
+    Path(id): Path<String>,
+    Json(req): Json<AddOrUpdateAlertRuleRequest>,
+) -> eyre::Result<ApiResponse, ApiResponse> {
+    check_rule_scope(&state, &user, &id)?;
+    check_scope(&state, &user, req.server_id.as_ref())?;
+    let rule = to_rule(&state, id, req)?;
+    state
+        .db_driver
+        .update_alert_rule(rule)
+        .map(|old| ApiResponse::ok("", Some(json!({"old":old}))))
+        .map_err(|e| ApiResponse::internal(&e.to_string()))
+}
+
+async fn delete_rule(
+    State(state): State<SharedState>,
+    Extension(user): Extension<User>,
+    Path(id): Path<String>,
+) -> eyre::Result<ApiResponse, ApiResponse> {
+    check_rule_scope(&state, &user, &id)?;
+    state
+        .db_driver
+        .delete_alert_rule(id)
+        .map(|_| ApiResponse::ok("", None))
+        .map_err(|e| ApiResponse::bad_request(e.to_string()))
+}
+
+/// the ids of the servers the user has access to, `None` if the user isn't scoped
+fn visible_servers(
+    state: &SharedState,
+    user: &User,
+) -> eyre::Result<Option<HashSet<String>>, ApiResponse> {
+    if !user.is_scoped() {
+        return Ok(None);
+    }
+    let servers = state
+        .db_driver
+        .all_servers()
+        .map_err(|e| ApiResponse::internal(&e.to_string()))?
+        .into_iter()
+        .filter(|s| user.can_access(s))
+        .map(|s| s.id)
+        .collect();
+    Ok(Some(servers))
+}
+
+/// scoped users can only manage the rules of their servers, a global rule covers every server
+fn check_scope(
+    state: &SharedState,
+    user: &User,
+    server_id: Option<&String>,
+) -> eyre::Result<(), ApiResponse> {
+    let Some(visible) = visible_servers(state, user)? else {
+        return Ok(());
+    };
+    match server_id {
+        Some(server_id) if visible.contains(server_id) => Ok(()),
+        Some(_) => Err(ApiResponse::forbidden(
+            "you don't have access to this server",
+        )),
+        None => Err(ApiResponse::forbidden(
+            "global rules are not available to users limited to server groups",
+        )),
+    }
+}
+
+fn check_rule_scope(state: &SharedState, user: &User, id: &str) -> eyre::Result<(), ApiResponse> {
+    if !user.is_scoped() {
+        return Ok(());
+    }
+    let rule = state
+        .db_driver
+        .get_alert_rule(id)
+        .map_err(|e| ApiResponse::internal(&e.to_string()))?
+        .ok_or(ApiResponse::bad_request("rule not found"))?;
+    check_scope(state, user, rule.server_id.as_ref())
+}
+
+fn to_rule(
+    state: &SharedState,
+    id: String,
+    req: AddOrUpdateAlertRuleRequest,
+) -> eyre::Result<AlertRule, ApiResponse> {
+    if req.name.trim().is_empty() {
+        return Err(ApiResponse::bad_request("name is required"));
+    }
+    if let Some(server_id) = &req.server_id {
+        state
+            .db_driver
+            .get_server_by_id(server_id.clone())
+            .map_err(|e| ApiResponse::internal(&e.to_string()))?
+            .ok_or(ApiResponse::bad_request("server not found"))?;
+    }
+    for channel in &req.channels {
+        state
+            .db_driver
+            .get_notification_channel(channel.clone())
+            .map_err(|e| ApiResponse::internal(&e.to_string()))?
+            .ok_or(ApiResponse::bad_request(format!(
+                "channel {channel} not found"
+            )))?;
+    }
+    Ok(AlertRule {
+        id,
+        name: req.name.trim().to_owned(),
+        server_id: req.server_id,
+        condition: req.condition,
+        for_secs: req.for_secs,
+        enabled: req.enabled,
+        channels: req.channels,
+    })
+}
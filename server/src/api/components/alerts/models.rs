@@ -0,0 +1,29 @@
+use crate::models::alert::AlertCondition;
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+pub struct AddOrUpdateAlertRuleRequest {
+    pub name: String,
+    /// `None` applies the rule to every server
+    #[serde(default)]
+    pub server_id: Option<String>,
+    pub condition: AlertCondition,
+    #[serde(default)]
+    pub for_secs: u64,
+    #[serde(default = "enabled_by_default")]
+    pub enabled: bool,
+    /// ids of the notification channels
+    #[serde(default)]
+    pub channels: Vec<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct AlertsQuery {
+    /// include the resolved alerts as well
+    pub all: bool,
+}
+
+fn enabled_by_default() -> bool {
+    true
+}
@@ -0,0 +1,55 @@
+use crate::api::components::audit::models::AuditQuery;
+use crate::libs::api_response::ApiResponse;
+use crate::libs::shared_state::SharedState;
+use crate::middlewares::rbac_mw::require_admin;
+use axum::extract::{Query, State};
+use axum::middleware::from_fn;
+use axum::routing::get;
+use axum::Router;
+use chrono::{TimeDelta, Utc};
+use serde_json::json;
+
+pub mod models;
+
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 1000;
+
+pub fn routes(state: SharedState) -> Router {
+    Router::new()
+        .route("/", get(get_entries))
+        .route_layer(from_fn(require_admin))
+        .with_state(state.clone())
+}
+
+/// the newest entries first
+async fn get_entries(
+    State(state): State<SharedState>,
+    Query(query): Query<AuditQuery>,
+) -> eyre::Result<ApiResponse, ApiResponse> {
+    let to = query.to.unwrap_or_else(Utc::now).naive_utc();
+    let from = query
+        .from
+        .map(|f| f.naive_utc())
+        .unwrap_or(to - TimeDelta::days(7));
+    if from > to {
+        return Err(ApiResponse::bad_request("`from` must be before `to`"));
+    }
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let action = query.action.map(|a| a.to_lowercase());
+
+    state
+        .db_driver
+        .get_audit_entries(from, to, limit, |e| {
+            query.username.as_ref().is_none_or(|u| e.username.eq(u))
+                && query
+                    .server_id
+                    .as_ref()
+                    .is_none_or(|id| e.server_id.as_ref() == Some(id))
+                && action
+                    .as_ref()
+                    .is_none_or(|a| e.action.to_lowercase().contains(a))
+                && query.success.is_none_or(|s| e.success == s)
+        })
+        .map(|entries| ApiResponse::ok("", Some(json!(entries))))
+        .map_err(|e| ApiResponse::internal(&e.to_string()))
+}
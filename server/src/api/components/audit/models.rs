@@ -0,0 +1,18 @@
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct AuditQuery {
+    /// defaults to 7 days before `to`
+    pub from: Option<DateTime<Utc>>,
+    /// defaults to now
+    pub to: Option<DateTime<Utc>>,
+    pub username: Option<String>,
+    pub server_id: Option<String>,
+    /// matched against the method and the route, e.g. `services` or `DELETE /servers`
+    pub action: Option<String>,
+    pub success: Option<bool>,
+    /// defaults to 100
+    pub limit: Option<usize>,
+}
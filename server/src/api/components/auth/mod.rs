@@ -0,0 +1,59 @@
+use crate::api::components::auth::models::{LoginRequest, RefreshRequest};
+use crate::libs::api_response::ApiResponse;
+use crate::libs::auth::{self, TokenType};
+use crate::libs::shared_state::SharedState;
+use axum::extract::State;
+use axum::routing::post;
+use axum::{Json, Router};
+use serde_json::json;
+
+pub mod models;
+
+pub fn routes(state: SharedState) -> Router {
+    Router::new()
+        .route("/login", post(login))
+        .route("/refresh", post(refresh))
+        .with_state(state.clone())
+}
+
+async fn login(
+    State(state): State<SharedState>,
+    Json(req): Json<LoginRequest>,
+) -> eyre::Result<ApiResponse, ApiResponse> {
+    let user = state
+        .db_driver
+        .get_user_by_username(req.username.trim())
+        .map_err(|e| ApiResponse::internal(&e.to_string()))?;
+    // same error for an unknown user and a wrong password
+    let valid = auth::verify_user_password(&req.password, user.as_ref());
+    let user = user
+        .filter(|_| valid)
+        .ok_or(ApiResponse::unauthorized("invalid username or password"))?;
+
+    auth::create_token_pair(&state.app_config.jwt_secret, &user.id, &user.username)
+        .map(|tokens| ApiResponse::ok("", Some(json!(tokens))))
+        .map_err(|e| ApiResponse::internal(&e.to_string()))
+}
+
+async fn refresh(
+    State(state): State<SharedState>,
+    Json(req): Json<RefreshRequest>,
+) -> eyre::Result<ApiResponse, ApiResponse> {
+    let claims = auth::decode_token(
+        &state.app_config.jwt_secret,
+        &req.refresh_token,
+        TokenType::Refresh,
+    )
+    .map_err(|_| ApiResponse::unauthorized("invalid refresh token"))?;
+
+    // the user might have been deleted since the token was issued
+    let user = state
+        .db_driver
+        .get_user_by_id(&claims.sub)
+        .map_err(|e| ApiResponse::internal(&e.to_string()))?
+        .ok_or(ApiResponse::unauthorized("invalid refresh token"))?;
+
+    auth::create_token_pair(&state.app_config.jwt_secret, &user.id, &user.username)
+        .map(|tokens| ApiResponse::ok("", Some(json!(tokens))))
+        .map_err(|e| ApiResponse::internal(&e.to_string()))
+}
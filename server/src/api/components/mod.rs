@@ -1,4 +1,5 @@
 use crate::libs::shared_state::SharedState;
+use crate::middlewares::audit_mw;
 use crate::middlewares::auth_mw::require_authentication;
 use axum::middleware::{from_fn_with_state};
 use axum::response::{IntoResponse};
@@ -6,11 +7,17 @@ use axum::routing::get;
 use axum::Router;
 
 pub mod agents;
+pub mod alerts;
+pub mod audit;
+pub mod auth;
+pub mod notifications;
 pub mod servers;
+pub mod users;
 
 pub fn routes(state: SharedState) -> Router {
     Router::new()
         .merge(authorized_routes(state.clone()))
+        .nest("/auth", auth::routes(state.clone()))
         .route("/ping", get(root))
 }
 
@@ -18,6 +25,11 @@ fn authorized_routes(state: SharedState) -> Router {
     Router::new()
         .nest("/servers", servers::routes(state.clone()))
         .nest("/agents", agents::routes(state.clone()))
+        .nest("/alerts", alerts::routes(state.clone()))
+        .nest("/notifications", notifications::routes(state.clone()))
+        .nest("/users", users::routes(state.clone()))
+        .nest("/audit", audit::routes(state.clone()))
+        .route_layer(from_fn_with_state(state.clone(), audit_mw::audit))
         .layer(from_fn_with_state(state.clone(), require_authentication))
 }
 
@@ -25,17 +37,3 @@ pub(crate) async fn root() -> impl IntoResponse {
     "UP".into_response()
 }
 
-
-/*pub async fn authorize_servers(State(state): State<SharedState>, mut req: Request, next: Next) -> eyre::Result<Response, ApiResponse> {
-    let token = req.headers()
-        .get(header::AUTHORIZATION)
-        .and_then(|value| value.to_str().ok().map(|f| f.replace("Bearer ", "")))
-        .ok_or_else(|| ApiResponse::unauthorized("authorization header is required"))?;
-
-
-    let claims = decode::<TokenClaims>(&token, &DecodingKey::from_secret(&state.app_config.pwd.as_bytes()), V.deref())
-        .map_err(|_| ApiResponse::unauthorized("invalid token"))?;
-
-    req.extensions_mut().insert(claims.claims);
-    Ok(next.run(req).await)
-}*/
\ No newline at end of file
@@ -0,0 +1,120 @@
+use crate::api::components::notifications::models::{AddOrUpdateChannelRequest, ChannelResponse};
+use crate::libs::api_response::ApiResponse;
+use crate::libs::notifier::Notification;
+use crate::libs::secret_box::SealedSecret;
+use crate::libs::shared_state::SharedState;
+use crate::middlewares::rbac_mw::{require_operator_for_writes, require_unscoped_for_writes};
+use crate::models::notification_channel::NotificationChannel;
+use axum::extract::{Path, State};
+use axum::middleware::from_fn;
+use axum::routing::{get, post, put};
+use axum::{Json, Router};
+use serde_json::json;
+
+pub mod models;
+
+pub fn routes(state: SharedState) -> Router {
+    Router::new()
+        .route("/channels", get(get_channels).post(add_channel))
+        .route("/channels/{id}", put(update_channel).delete(delete_channel))
+        .route("/channels/{id}/test", post(test_channel))
+        .route_layer(from_fn(require_unscoped_for_writes))
+        .route_layer(from_fn(require_operator_for_writes))
+        .with_state(state.clone())
+}
+
+async fn get_channels(State(state): State<SharedState>) -> ApiResponse {
+    state
+        .db_driver
+        .all_notification_channels()
+        .map(|c| {
+            let channels = c.into_iter().map(ChannelResponse::from).collect::<Vec<_>>();
+            ApiResponse::ok("", Some(json!(channels)))
+        })
+        .map_err(|e| ApiResponse::internal(&e.to_string()))
+        .into()
+}
+
+async fn add_channel(
+    State(state): State<SharedState>,
+    Json(req): Json<AddOrUpdateChannelRequest>,
+) -> eyre::Result<ApiResponse, ApiResponse> {
+    let id = cuid2::create_id();
+    let channel = to_channel(id.clone(), req, None)?;
+    state
+        .db_driver
+        .upsert_notification_channel(channel)
+        .map(|_| ApiResponse::ok("", Some(json!({"id":id}))))
+        .map_err(|e| ApiResponse::internal(&e.to_string()))
+}
+
+async fn update_channel(
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+    Json(req): Json<AddOrUpdateChannelRequest>,
+) -> eyre::Result<ApiResponse, ApiResponse> {
+    let current = state
+        .db_driver
+        .get_notification_channel(id.clone())
+        .map_err(|e| ApiResponse::internal(&e.to_string()))?
+        .ok_or(ApiResponse::bad_request("channel not found"))?;
+
+    let channel = to_channel(id, req, current.secret)?;
+    state
+        .db_driver
+        .upsert_notification_channel(channel)
+        .map(|_| ApiResponse::ok("", None))
+        .map_err(|e| ApiResponse::internal(&e.to_string()))
+}
+
+async fn delete_channel(State(state): State<SharedState>, Path(id): Path<String>) -> ApiResponse {
+    state
+        .db_driver
+        .delete_notification_channel(id)
+        .map(|_| ApiResponse::ok("", None))
+        .map_err(|e| ApiResponse::bad_request(e.to_string()))
+        .into()
+}
+
+/// send a test notification to the channel and report the delivery error if there is any
+async fn test_channel(
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+) -> eyre::Result<ApiResponse, ApiResponse> {
+    let channel = state
+        .db_driver
+        .get_notification_channel(id)
+        .map_err(|e| ApiResponse::internal(&e.to_string()))?
+        .ok_or(ApiResponse::bad_request("channel not found"))?;
+
+    state
+        .notifier
+        .send(&channel, &Notification::test())
+        .await
+        .map(|_| ApiResponse::ok("", None))
+        .map_err(|e| ApiResponse::bad_request(e.to_string()))
+}
+
+fn to_channel(
+    id: String,
+    req: AddOrUpdateChannelRequest,
+    current_secret: Option<SealedSecret>,
+) -> eyre::Result<NotificationChannel, ApiResponse> {
+    if req.name.trim().is_empty() {
+        return Err(ApiResponse::bad_request("name is required"));
+    }
+    let secret = match req.password {
+        Some(password) => {
+            Some(SealedSecret::seal(&password).map_err(|e| ApiResponse::internal(&e.to_string()))?)
+        }
+        None => current_secret,
+    };
+    Ok(NotificationChannel {
+        id,
+        name: req.name.trim().to_owned(),
+        kind: req.kind,
+        secret,
+        agent_events: req.agent_events,
+        enabled: req.enabled,
+    })
+}
@@ -0,0 +1,43 @@
+use crate::models::notification_channel::{ChannelKind, NotificationChannel};
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize)]
+pub struct AddOrUpdateChannelRequest {
+    pub name: String,
+    pub kind: ChannelKind,
+    /// the smtp password, write-only. on update the current one is kept if this is not set
+    #[serde(default)]
+    pub password: Option<String>,
+    #[serde(default)]
+    pub agent_events: bool,
+    #[serde(default = "enabled_by_default")]
+    pub enabled: bool,
+}
+
+/// the public view of a [`NotificationChannel`], the password is never returned
+#[derive(Serialize)]
+pub struct ChannelResponse {
+    pub id: String,
+    pub name: String,
+    pub kind: ChannelKind,
+    pub has_password: bool,
+    pub agent_events: bool,
+    pub enabled: bool,
+}
+
+impl From<NotificationChannel> for ChannelResponse {
+    fn from(value: NotificationChannel) -> Self {
+        Self {
+            id: value.id,
+            name: value.name,
+            kind: value.kind,
+            has_password: value.secret.is_some(),
+            agent_events: value.agent_events,
+            enabled: value.enabled,
+        }
+    }
+}
+
+fn enabled_by_default() -> bool {
+    true
+}
@@ -0,0 +1,263 @@
+use crate::api::components::servers::models::{ExecRequest, FleetExecRequest};
+use crate::api::HANDLER_TIMEOUT;
+use crate::libs::api_response::ApiResponse;
+use crate::libs::audit::{self, AuditRecord};
+use crate::libs::fleet_exec::FleetCommand;
+use crate::libs::shared_state::SharedState;
+use crate::libs::ssh_session::{self, OutputStream};
+use crate::models::server::Server;
+use crate::models::user::User;
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::Response;
+use axum::{Extension, Json};
+use serde_json::json;
+use std::collections::HashSet;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+const DEFAULT_EXEC_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_STREAM_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+const DEFAULT_FLEET_CONCURRENCY: usize = 10;
+const MAX_FLEET_CONCURRENCY: usize = 64;
+
+pub(super) async fn exec(
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+    Json(req): Json<ExecRequest>,
+) -> eyre::Result<ApiResponse, ApiResponse> {
+    if req.command.trim().is_empty() {
+        return Err(ApiResponse::bad_request("command is required"));
+    }
+    let timeout = req
+        .timeout_secs
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_EXEC_TIMEOUT)
+        // longer commands should use the websocket endpoint
+        .min(HANDLER_TIMEOUT);
+
+    let server = find_server(&state, id)?;
+    let mut ssh = ssh_session::connect(&server, &state.db_driver)
+        .await
+        .map_err(|e| ApiResponse::bad_request(e.to_string()))?;
+
+    let output = ssh
+        .exec(
+            &req.command,
+            req.stdin.as_ref().map(|s| s.as_bytes()),
+            timeout,
+        )
+        .await;
+    let _ = ssh.close().await;
+
+    output
+        .map(|o| ApiResponse::ok("", Some(json!(o))))
+        .map_err(|e| ApiResponse::internal(&e.to_string()))
+}
+
+/// run the command on every selected server in the background,
+/// the results can be polled via `GET /servers/exec/{run_id}`
+pub(super) async fn fleet_exec(
+    State(state): State<SharedState>,
+    Extension(user): Extension<User>,
+    Json(req): Json<FleetExecRequest>,
+) -> eyre::Result<ApiResponse, ApiResponse> {
+    if req.command.trim().is_empty() {
+        return Err(ApiResponse::bad_request("command is required"));
+    }
+    if req.server_ids.is_empty() && req.tags.is_empty() {
+        return Err(ApiResponse::bad_request(
+            "at least one server id or tag is required",
+        ));
+    }
+
+    let servers = state
+        .db_driver
+        .all_servers()
+        .map_err(|e| ApiResponse::internal(&e.to_string()))?
+        .into_iter()
+        .filter(|s| req.server_ids.contains(&s.id) || s.tags.iter().any(|t| req.tags.contains(t)))
+        .filter(|s| user.can_access(s))
+        .collect::<Vec<_>>();
+
+    if servers.is_empty() {
+        return Err(ApiResponse::bad_request("no server matched the selection"));
+    }
+
+    let command = FleetCommand {
+        command: req.command,
+        stdin: req.stdin,
+        timeout: req
+            .timeout_secs
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_EXEC_TIMEOUT)
+            .min(MAX_STREAM_TIMEOUT),
+        concurrency: req
+            .concurrency
+            .unwrap_or(DEFAULT_FLEET_CONCURRENCY)
+            .clamp(1, MAX_FLEET_CONCURRENCY),
+    };
+    let targets = servers.iter().map(|s| s.id.clone()).collect::<Vec<_>>();
+    let id = state
+        .fleet_exec
+        .start(state.db_driver.clone(), servers, command);
+
+    Ok(ApiResponse::ok(
+        "",
+        Some(json!({"id":id,"targets":targets})),
+    ))
+}
+
+/// scoped users only see the hosts they have access to
+pub(super) async fn get_fleet_run(
+    State(state): State<SharedState>,
+    Extension(user): Extension<User>,
+    Path(run_id): Path<String>,
+) -> eyre::Result<ApiResponse, ApiResponse> {
+    let mut run = state
+        .fleet_exec
+        .get(&run_id)
+        .ok_or(ApiResponse::bad_request("run not found"))?;
+
+    if user.is_scoped() {
+        let visible = state
+            .db_driver
+            .all_servers()
+            .map_err(|e| ApiResponse::internal(&e.to_string()))?
+            .into_iter()
+            .filter(|s| user.can_access(s))
+            .map(|s| s.id)
+            .collect::<HashSet<_>>();
+        run.pending.retain(|id| visible.contains(id));
+        run.results.retain(|r| visible.contains(&r.server_id));
+    }
+    Ok(ApiResponse::ok("", Some(json!(run))))
+}
+
+/// the first text message of the client must be an [`ExecRequest`],
+/// the output is streamed back as `{"type":"stdout"|"stderr","data":".."}` frames
+/// and the socket is closed after an `{"type":"exit","code":..}` or `{"type":"error","message":".."}` frame
+pub(super) async fn exec_stream(
+    State(state): State<SharedState>,
+    Extension(user): Extension<User>,
+    Path(id): Path<String>,
+    ws: WebSocketUpgrade,
+) -> eyre::Result<Response, ApiResponse> {
+    let server = find_server(&state, id)?;
+    Ok(ws.on_upgrade(move |socket| async move {
+        if let Err(e) = stream_command(state, user, server, socket).await {
+            log::error!("exec stream failed: {e}");
+        }
+    }))
+}
+
+async fn stream_command(
+    state: SharedState,
+    user: User,
+    server: Server,
+    mut socket: WebSocket,
+) -> eyre::Result<()> {
+    let (req, audit_params) = loop {
+        match socket.recv().await {
+            Some(Ok(Message::Text(text))) => {
+                let req = serde_json::from_str::<ExecRequest>(&text)?;
+                break (req, audit::redacted_params(text.as_bytes()));
+            }
+            Some(Ok(Message::Close(_))) | None => return Ok(()),
+            Some(Ok(_)) => continue,
+            Some(Err(e)) => return Err(e.into()),
+        }
+    };
+    let timeout = req
+        .timeout_secs
+        .map(Duration::from_secs)
+        .unwrap_or(MAX_STREAM_TIMEOUT)
+        .min(MAX_STREAM_TIMEOUT);
+
+    // the upgrade itself isn't audited, the command is only known at this point.
+    // it is recorded before it runs so closing the socket can't hide it, the result is set once it finishes
+    let audit_entry = audit::record(
+        &state.db_driver,
+        AuditRecord {
+            user: &user,
+            action: "WS /servers/{id}/exec/ws".into(),
+            path: format!("/servers/{}/exec/ws", server.id),
+            server_id: Some(server.id.clone()),
+            params: audit_params,
+            status: StatusCode::SWITCHING_PROTOCOLS.as_u16(),
+            success: false,
+        },
+    );
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let task = tokio::spawn(async move {
+        let data_tx = tx.clone();
+        let result = async {
+            let mut ssh = ssh_session::connect(&server, &state.db_driver).await?;
+            let call = ssh.call_with_stdin(
+                &req.command,
+                req.stdin.as_ref().map(|s| s.as_bytes()),
+                move |stream, data| {
+                    let data_tx = data_tx.clone();
+                    async move {
+                        let frame = match stream {
+                            OutputStream::Stdout => {
+                                json!({"type":"stdout","data":String::from_utf8_lossy(&data)})
+                            }
+                            OutputStream::Stderr => {
+                                json!({"type":"stderr","data":String::from_utf8_lossy(&data)})
+                            }
+                        };
+                        data_tx.send(frame)?;
+                        Ok(())
+                    }
+                },
+            );
+            let code = tokio::time::timeout(timeout, call).await.map_err(|_| {
+                eyre::eyre!("command timed out after {} seconds", timeout.as_secs())
+            })??;
+            let _ = ssh.close().await;
+            eyre::Result::<Option<u32>>::Ok(code)
+        }
+        .await;
+
+        if let Some(entry) = audit_entry {
+            audit::finish(&state.db_driver, entry, matches!(result, Ok(Some(0))));
+        }
+
+        let frame = match result {
+            Ok(code) => json!({"type":"exit","code":code}),
+            Err(e) => json!({"type":"error","message":e.to_string()}),
+        };
+        let _ = tx.send(frame);
+    });
+
+    loop {
+        tokio::select! {
+            frame = rx.recv() => {
+                let Some(frame) = frame else {
+                    break;
+                };
+                socket.send(Message::Text(frame.to_string().into())).await?;
+            }
+            incoming = socket.recv() => {
+                // the client went away, there is no one to stream the output to
+                if matches!(incoming, None | Some(Ok(Message::Close(_))) | Some(Err(_))) {
+                    task.abort();
+                    return Ok(());
+                }
+            }
+        }
+    }
+    let _ = socket.send(Message::Close(None)).await;
+    Ok(())
+}
+
+fn find_server(state: &SharedState, id: String) -> eyre::Result<Server, ApiResponse> {
+    state
+        .db_driver
+        .get_server_by_id(id)
+        .map_err(|e| ApiResponse::internal(&e.to_string()))?
+        .ok_or(ApiResponse::bad_request("server not found"))
+}
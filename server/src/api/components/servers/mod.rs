@@ -1,23 +1,50 @@
-use crate::api::components::servers::models::AddOrUpdateServerRequest;
+use crate::api::components::servers::models::{
+    AcceptHostKeyRequest, AddOrUpdateServerRequest, DeleteServerQuery, MetricsQuery, ServerResponse,
+};
 use crate::libs::api_response::ApiResponse;
+use crate::libs::secret_box::SealedSecret;
 use crate::libs::shared_state::SharedState;
-use crate::models::server::Server;
-use axum::extract::{Path, State};
-use axum::routing::get;
-use axum::{Json, Router};
+use crate::libs::{deploy_jobs, metric_aggregator, ssh_session};
+use crate::middlewares::rbac_mw::authorize_servers;
+use crate::models::server::{Server, ServerSecret};
+use crate::models::user::User;
+use axum::extract::{Path, Query, State};
+use axum::middleware::from_fn_with_state;
+use axum::routing::{get, post, put};
+use axum::{Extension, Json, Router};
+use chrono::{TimeDelta, Utc};
 use serde_json::json;
 use std::net::Ipv4Addr;
 use std::str::FromStr;
 
+mod exec;
 pub mod models;
+mod services;
+
+/// the longest range of `GET /servers/{id}/metrics`
+const MAX_METRICS_RANGE: TimeDelta = TimeDelta::days(31);
 
 pub fn routes(state: SharedState) -> Router {
     Router::new()
         .route("/", get(get_servers).post(add_server))
+        .route("/exec", post(exec::fleet_exec))
+        .route("/exec/{run_id}", get(exec::get_fleet_run))
         .route(
             "/{id}",
             get(get_by_id).put(update_server).delete(delete_server),
         )
+        .route("/{id}/secret", put(rotate_secret))
+        .route("/{id}/exec", post(exec::exec))
+        .route("/{id}/exec/ws", get(exec::exec_stream))
+        .route("/{id}/services", get(services::list_services))
+        .route(
+            "/{id}/services/{unit}/{action}",
+            post(services::manage_service),
+        )
+        .route("/{id}/host-key", get(get_host_key).put(accept_host_key))
+        .route("/{id}/metrics", get(get_metrics))
+        .route("/{id}/metrics/latest", get(get_latest_metric))
+        .route_layer(from_fn_with_state(state.clone(), authorize_servers))
         .with_state(state.clone())
 }
 async fn get_by_id(State(state): State<SharedState>, Path(id): Path<String>) -> ApiResponse {
@@ -25,40 +52,109 @@ async fn get_by_id(State(state): State<SharedState>, Path(id): Path<String>) ->
         .db_driver
         .get_server_by_id(id)
         .map(|a| {
-            a.map(|s| ApiResponse::ok("", Some(json!(s))))
-                .unwrap_or(ApiResponse::bad_request("server not found"))
+            a.map(|s| {
+                let server = ServerResponse::new(s, &state.agent_hub);
+                ApiResponse::ok("", Some(json!(server)))
+            })
+            .unwrap_or(ApiResponse::bad_request("server not found"))
         })
         .map_err(|e| ApiResponse::internal(&e.to_string()))
         .into()
 }
 
-async fn delete_server(State(state): State<SharedState>, Path(id): Path<String>) -> ApiResponse {
+async fn delete_server(
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+    Query(query): Query<DeleteServerQuery>,
+) -> ApiResponse {
+    if query.uninstall_agent {
+        return uninstall_and_delete(&state, id).into();
+    }
     state
         .db_driver
-        .delete_server(id)
+        .delete_server(id.clone())
+        // the connection was authenticated once, revoking the token doesn't close it
+        .map(|_| state.agent_hub.disconnect(&id))
         .map(|_| ApiResponse::ok("", None))
         .map_err(|e| ApiResponse::internal(&e.to_string()))
         .into()
 }
 
+/// the removal of the agent runs as a job, the response carries it
+fn uninstall_and_delete(state: &SharedState, id: String) -> eyre::Result<ApiResponse, ApiResponse> {
+    let server = state
+        .db_driver
+        .get_server_by_id(id)
+        .map_err(|e| ApiResponse::internal(&e.to_string()))?
+        .ok_or(ApiResponse::bad_request("server not found"))?;
+    let Some(guard) = deploy_jobs::begin(&server.id) else {
+        return Err(ApiResponse::conflict(
+            "a job is already running on the server",
+        ));
+    };
+    let job = deploy_jobs::start_uninstall(state, server, true, guard)
+        .map_err(|e| ApiResponse::internal(&e.to_string()))?;
+    Ok(ApiResponse::ok("", Some(json!(job))))
+}
+
 async fn update_server(
     State(state): State<SharedState>,
     Path(id): Path<String>,
     Json(req): Json<AddOrUpdateServerRequest>,
-) -> ApiResponse {
+) -> eyre::Result<ApiResponse, ApiResponse> {
+    let current = state
+        .db_driver
+        .get_server_by_id(id)
+        .map_err(|e| ApiResponse::internal(&e.to_string()))?
+        .ok_or(ApiResponse::bad_request("server not found"))?;
+
     state
         .db_driver
-        .update_server(Server::from_req(id, req))
-        .map(|r| ApiResponse::ok("", Some(json!({"old":r}))))
+        .update_server(Server::from_req(current, req))
+        .map(|r| {
+            let old = r.map(|s| ServerResponse::new(s, &state.agent_hub));
+            ApiResponse::ok("", Some(json!({"old":old})))
+        })
         .map_err(|e| ApiResponse::internal(&e.to_string()))
-        .into()
 }
 
-async fn get_servers(State(state): State<SharedState>) -> ApiResponse {
+/// write-only, replaces the credentials that are used to connect to the server
+async fn rotate_secret(
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+    Json(secret): Json<ServerSecret>,
+) -> eyre::Result<ApiResponse, ApiResponse> {
+    let mut server = state
+        .db_driver
+        .get_server_by_id(id)
+        .map_err(|e| ApiResponse::internal(&e.to_string()))?
+        .ok_or(ApiResponse::bad_request("server not found"))?;
+
+    server.secret =
+        SealedSecret::seal(&secret).map_err(|e| ApiResponse::internal(&e.to_string()))?;
+
+    state
+        .db_driver
+        .update_server(server)
+        .map(|_| ApiResponse::ok("", None))
+        .map_err(|e| ApiResponse::internal(&e.to_string()))
+}
+
+async fn get_servers(
+    State(state): State<SharedState>,
+    Extension(user): Extension<User>,
+) -> ApiResponse {
     state
         .db_driver
         .all_servers()
-        .map(|f| ApiResponse::ok("", Some(json!(f))))
+        .map(|f| {
+            let servers = f
+                .into_iter()
+                .filter(|s| user.can_access(s))
+                .map(|s| ServerResponse::new(s, &state.agent_hub))
+                .collect::<Vec<_>>();
+            ApiResponse::ok("", Some(json!(servers)))
+        })
         .map_err(|e| ApiResponse::bad_request(e.to_string()))
         .into()
 }
@@ -84,6 +180,9 @@ async fn add_server(
         20
     };
 
+    let secret =
+        SealedSecret::seal(&req.secret).map_err(|e| ApiResponse::internal(&e.to_string()))?;
+
     let id = cuid2::create_id();
     state
         .db_driver
@@ -93,8 +192,125 @@ async fn add_server(
             name: req.name.trim().to_owned(),
             ip: ip.to_string(),
             id: id.clone(),
-            secret: req.secret,
+            secret,
+            tags: req.tags,
+            last_seen: None,
+            arch: None,
+            escalation: req.escalation,
         })
         .map(|_| ApiResponse::ok("", Some(json!({"id":id}))))
         .map_err(|e| ApiResponse::bad_request(e.to_string()))
 }
+
+async fn get_latest_metric(
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+) -> ApiResponse {
+    state
+        .db_driver
+        .get_latest_metric(&id)
+        .map(|m| {
+            m.map(|m| ApiResponse::ok("", Some(json!(m))))
+                .unwrap_or(ApiResponse::bad_request("no metrics found"))
+        })
+        .map_err(|e| ApiResponse::internal(&e.to_string()))
+        .into()
+}
+
+async fn get_metrics(
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+    Query(query): Query<MetricsQuery>,
+) -> eyre::Result<ApiResponse, ApiResponse> {
+    let to = query.to.unwrap_or_else(Utc::now).naive_utc();
+    let from = query
+        .from
+        .map(|f| f.naive_utc())
+        .unwrap_or(to.checked_sub_signed(TimeDelta::hours(1)).unwrap_or(to));
+    if from > to {
+        return Err(ApiResponse::bad_request("`from` must be before `to`"));
+    }
+    if to - from > MAX_METRICS_RANGE {
+        return Err(ApiResponse::bad_request(format!(
+            "the range can't be longer than {} days",
+            MAX_METRICS_RANGE.num_days()
+        )));
+    }
+    let step = match query.step {
+        Some(0) => return Err(ApiResponse::bad_request("`step` must be greater than 0")),
+        Some(step) => Some(
+            i64::try_from(step)
+                .ok()
+                .and_then(TimeDelta::try_seconds)
+                .ok_or(ApiResponse::bad_request("`step` is too large"))?,
+        ),
+        None => None,
+    };
+
+    let metrics = state
+        .db_driver
+        .get_metrics_range(&id, from, to)
+        .map_err(|e| ApiResponse::internal(&e.to_string()))?;
+
+    let data = match step {
+        Some(step) => json!(metric_aggregator::downsample(&metrics, from, step)),
+        None => json!(metric_aggregator::samples(metrics)),
+    };
+
+    Ok(ApiResponse::ok("", Some(data)))
+}
+
+async fn get_host_key(State(state): State<SharedState>, Path(id): Path<String>) -> ApiResponse {
+    state
+        .db_driver
+        .get_known_host(&id)
+        .map(|k| ApiResponse::ok("", Some(json!(k))))
+        .map_err(|e| ApiResponse::internal(&e.to_string()))
+        .into()
+}
+
+/// pin the host key that the server currently presents, replacing the old one.
+///
+/// only the first key can be pinned blindly, replacing a pinned key requires its verified fingerprint
+async fn accept_host_key(
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+    Json(req): Json<AcceptHostKeyRequest>,
+) -> eyre::Result<ApiResponse, ApiResponse> {
+    let server = state
+        .db_driver
+        .get_server_by_id(id.clone())
+        .map_err(|e| ApiResponse::internal(&e.to_string()))?
+        .ok_or(ApiResponse::bad_request("server not found"))?;
+
+    let pinned = state
+        .db_driver
+        .get_known_host(&id)
+        .map_err(|e| ApiResponse::internal(&e.to_string()))?;
+    if pinned.is_some() && req.fingerprint.is_none() {
+        return Err(ApiResponse::bad_request(
+            "a key is already pinned, `fingerprint` of the new key is required",
+        ));
+    }
+
+    let host_key = ssh_session::fetch_host_key(&server.ip, server.port)
+        .await
+        .map_err(|e| ApiResponse::bad_request(e.to_string()))?;
+
+    if req
+        .fingerprint
+        .is_some_and(|f| !f.trim().eq(&host_key.fingerprint))
+    {
+        return Err(ApiResponse::conflict(&format!(
+            "the server presented a different key: {}",
+            host_key.fingerprint
+        )));
+    }
+
+    let known_host = host_key.to_known_host(&id);
+    state
+        .db_driver
+        .set_known_host(known_host.clone())
+        .map(|old| ApiResponse::ok("", Some(json!({"old":old,"new":known_host}))))
+        .map_err(|e| ApiResponse::internal(&e.to_string()))
+}
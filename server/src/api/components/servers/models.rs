@@ -1,5 +1,7 @@
-use crate::models::server::ServerSecret;
-use serde::Deserialize;
+use crate::libs::agent_hub::{AgentHub, AgentSession};
+use crate::models::server::{Escalation, Server, ServerSecret};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
 
 #[derive(Deserialize, Default)]
 #[serde(default)]
@@ -8,5 +10,106 @@ pub struct AddOrUpdateServerRequest {
     pub ip: String,
     pub port: Option<usize>,
     pub user: String,
+    /// ignored on update, use `PUT /servers/{id}/secret` to rotate it
     pub secret: ServerSecret,
+    pub tags: Vec<String>,
+    /// needed to deploy the agent when `user` isn't root
+    pub escalation: Escalation,
+}
+
+/// the public view of a [`Server`], the secret is never returned
+#[derive(Serialize)]
+pub struct ServerResponse {
+    pub id: String,
+    pub name: String,
+    pub ip: String,
+    pub port: usize,
+    pub user: String,
+    pub tags: Vec<String>,
+    pub arch: Option<String>,
+    pub escalation: Escalation,
+    pub status: AgentStatus,
+    pub last_seen: Option<NaiveDateTime>,
+    /// only set while the agent is online
+    pub agent: Option<AgentSession>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AgentStatus {
+    Online,
+    Offline,
+    NeverConnected,
+}
+
+impl ServerResponse {
+    pub fn new(server: Server, agent_hub: &AgentHub) -> Self {
+        let agent = agent_hub.session(&server.id);
+        let status = match (&agent, server.last_seen) {
+            (Some(_), _) => AgentStatus::Online,
+            (None, Some(_)) => AgentStatus::Offline,
+            (None, None) => AgentStatus::NeverConnected,
+        };
+        Self {
+            last_seen: agent.as_ref().map(|a| a.last_message).or(server.last_seen),
+            id: server.id,
+            name: server.name,
+            ip: server.ip,
+            port: server.port,
+            user: server.user,
+            tags: server.tags,
+            arch: server.arch,
+            escalation: server.escalation,
+            status,
+            agent,
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct MetricsQuery {
+    /// defaults to one hour before `to`
+    pub from: Option<DateTime<Utc>>,
+    /// defaults to now
+    pub to: Option<DateTime<Utc>>,
+    /// bucket size in seconds, when set the samples are downsampled to avg/min/max per bucket
+    pub step: Option<u64>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct DeleteServerQuery {
+    /// remove the agent from the server first, the server is deleted once that is done
+    pub uninstall_agent: bool,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct AcceptHostKeyRequest {
+    /// the fingerprint that was verified out of band, the key is only accepted when the server
+    /// presents exactly this key. required when a key is already pinned
+    pub fingerprint: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct ExecRequest {
+    pub command: String,
+    pub timeout_secs: Option<u64>,
+    pub stdin: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct FleetExecRequest {
+    pub server_ids: Vec<String>,
+    /// every server that has at least one of these tags is targeted as well
+    pub tags: Vec<String>,
+    pub command: String,
+    /// per server
+    pub timeout_secs: Option<u64>,
+    pub stdin: Option<String>,
+    /// how many servers are called at the same time
+    pub concurrency: Option<usize>,
 }
@@ -0,0 +1,48 @@
+use crate::api::HANDLER_TIMEOUT;
+use crate::libs::api_response::ApiResponse;
+use crate::libs::shared_state::SharedState;
+use agent_shared::{AgentRequest, AgentResponse, UnitAction};
+use axum::extract::{Path, State};
+use serde_json::json;
+
+pub(super) async fn list_services(
+    State(state): State<SharedState>,
+    Path(id): Path<String>,
+) -> eyre::Result<ApiResponse, ApiResponse> {
+    match send(&state, &id, AgentRequest::ListUnits).await? {
+        AgentResponse::Units { units } => Ok(ApiResponse::ok("", Some(json!(units)))),
+        response => Err(unexpected(response)),
+    }
+}
+
+/// `action` is one of `start`, `stop`, `restart`, `enable` or `disable`
+pub(super) async fn manage_service(
+    State(state): State<SharedState>,
+    Path((id, unit, action)): Path<(String, String, UnitAction)>,
+) -> eyre::Result<ApiResponse, ApiResponse> {
+    match send(&state, &id, AgentRequest::ManageUnit { unit, action }).await? {
+        AgentResponse::Done => Ok(ApiResponse::ok("", None)),
+        response => Err(unexpected(response)),
+    }
+}
+
+async fn send(
+    state: &SharedState,
+    server_id: &str,
+    request: AgentRequest,
+) -> eyre::Result<AgentResponse, ApiResponse> {
+    state
+        .agent_hub
+        .request(server_id, request, HANDLER_TIMEOUT)
+        .await
+        .map_err(|e| ApiResponse::bad_request(e.to_string()))
+}
+
+fn unexpected(response: AgentResponse) -> ApiResponse {
+    match response {
+        AgentResponse::Error { message } => ApiResponse::bad_request(message),
+        response => {
+            ApiResponse::internal(&format!("unexpected response from the agent: {response:?}"))
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use crate::api::components::users::models::{
+    AddUserRequest, ChangePasswordRequest, UpdateUserRequest, UserResponse,
+};
+use crate::libs::api_response::ApiResponse;
+use crate::libs::auth;
+use crate::libs::shared_state::SharedState;
+use crate::middlewares::rbac_mw::require_admin;
+use crate::models::user::{Role, User};
+use axum::extract::{Path, State};
+use axum::middleware::from_fn;
+use axum::routing::{get, put};
+use axum::{Extension, Json, Router};
+use chrono::Utc;
+use serde_json::json;
+
+pub mod models;
+
+const MIN_PASSWORD_LEN: usize = 8;
+
+pub fn routes(state: SharedState) -> Router {
+    Router::new()
+        .route("/", get(get_users).post(add_user))
+        .route("/{id}", put(update_user).delete(delete_user))
+        .route_layer(from_fn(require_admin))
+        // available to every user
+        .route("/me", get(get_me))
+        .route("/{id}/password", put(change_password))
+        .with_state(state.clone())
+}
+
+async fn get_users(State(state): State<SharedState>) -> ApiResponse {
+    state
+        .db_driver
+        .all_users()
+        .map(|users| {
+            let users = users
+                .into_iter()
+                .map(UserResponse::from)
+                .collect::<Vec<_>>();
+            ApiResponse::ok("", Some(json!(users)))
+        })
+        .map_err(|e| ApiResponse::internal(&e.to_string()))
+        .into()
+}
+
+async fn get_me(Extension(user): Extension<User>) -> ApiResponse {
+    ApiResponse::ok("", Some(json!(UserResponse::from(user))))
+}
+
+async fn add_user(
+    State(state): State<SharedState>,
+    Json(req): Json<AddUserRequest>,
+) -> eyre::Result<ApiResponse, ApiResponse> {
+    let username = req.username.trim();
+    if username.is_empty() {
+        return Err(ApiResponse::bad_request("username is required"));
+    }
+    validate_password(&req.password)?;
+    if state
+        .db_driver
+        .get_user_by_username(username)
+        .map_err(|e| ApiResponse::internal(&e.to_string()))?
+        .is_some()
+    {
+        return Err(ApiResponse::conflict("the username is already taken"));
+    }
+
+    let user = User {
+        id: cuid2::create_id(),
+        username: username.to_owned(),
+        password_hash: auth::hash_password(&req.password)
+            .map_err(|e| ApiResponse::internal(&e.to_string()))?,
+        created_at: Utc::now().naive_utc(),
+        role: req.role,
+        server_groups: req.server_groups,
+    };
+    let id = user.id.clone();
+    state
+        .db_driver
+        .add_user(user)
+        .map(|_| ApiResponse::ok("", Some(json!({"id":id}))))
+        .map_err(|e| ApiResponse::internal(&e.to_string()))
+}
+
+async fn update_user(
+    State(state): State<SharedState>,
+    Extension(current): Extension<User>,
+    Path(id): Path<String>,
+    Json(req): Json<UpdateUserRequest>,
+) -> eyre::Result<ApiResponse, ApiResponse> {
+    if current.id.eq(&id) && req.role != current.role {
+        return Err(ApiResponse::bad_request("you can't change your own role"));
+    }
+    let mut user = state
+        .db_driver
+        .get_user_by_id(&id)
+        .map_err(|e| ApiResponse::internal(&e.to_string()))?
+        .ok_or(ApiResponse::bad_request("user not found"))?;
+    user.role = req.role;
+    user.server_groups = req.server_groups;
+    state
+        .db_driver
+        .update_user(user)
+        .map(|_| ApiResponse::ok("", None))
+        .map_err(|e| ApiResponse::internal(&e.to_string()))
+}
+
+/// users can change their own password with their current one, admins the password of everyone
+async fn change_password(
+    State(state): State<SharedState>,
+    Extension(current): Extension<User>,
+    Path(id): Path<String>,
+    Json(req): Json<ChangePasswordRequest>,
+) -> eyre::Result<ApiResponse, ApiResponse> {
+    if !current.id.eq(&id) && current.role != Role::Admin {
+        return Err(ApiResponse::forbidden(
+            "this action requires the Admin role",
+        ));
+    }
+    validate_password(&req.password)?;
+    let mut user = state
+        .db_driver
+        .get_user_by_id(&id)
+        .map_err(|e| ApiResponse::internal(&e.to_string()))?
+        .ok_or(ApiResponse::bad_request("user not found"))?;
+    // an access token alone must not be enough to take the account over
+    if current.id.eq(&id) {
+        let current_password = req.current_password.as_deref().unwrap_or_default();
+        if !auth::verify_password(current_password, &user.password_hash) {
+            return Err(ApiResponse::bad_request("the current password is wrong"));
+        }
+    }
+    user.password_hash =
+        auth::hash_password(&req.password).map_err(|e| ApiResponse::internal(&e.to_string()))?;
+    state
+        .db_driver
+        .update_user(user)
+        .map(|_| ApiResponse::ok("", None))
+        .map_err(|e| ApiResponse::internal(&e.to_string()))
+}
+
+async fn delete_user(
+    State(state): State<SharedState>,
+    Extension(current): Extension<User>,
+    Path(id): Path<String>,
+) -> eyre::Result<ApiResponse, ApiResponse> {
+    if current.id.eq(&id) {
+        return Err(ApiResponse::bad_request("you can't delete your own user"));
+    }
+    state
+        .db_driver
+        .delete_user(&id)
+        .map(|_| ApiResponse::ok("", None))
+        .map_err(|e| ApiResponse::bad_request(e.to_string()))
+}
+
+fn validate_password(password: &str) -> eyre::Result<(), ApiResponse> {
+    if password.chars().count() < MIN_PASSWORD_LEN {
+        return Err(ApiResponse::bad_request(format!(
+            "the password must be at least {MIN_PASSWORD_LEN} characters long"
+        )));
+    }
+    Ok(())
+}
@@ -0,0 +1,50 @@
+use crate::models::user::{Role, User};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize)]
+pub struct AddUserRequest {
+    pub username: String,
+    pub password: String,
+    pub role: Role,
+    /// tags of the servers the user can access, empty for every server
+    #[serde(default)]
+    pub server_groups: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct UpdateUserRequest {
+    pub role: Role,
+    #[serde(default)]
+    pub server_groups: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ChangePasswordRequest {
+    pub password: String,
+    /// required to change your own password, not when an admin resets the password of someone else
+    #[serde(default)]
+    pub current_password: Option<String>,
+}
+
+/// the public view of a [`User`], without the password hash
+#[derive(Serialize)]
+pub struct UserResponse {
+    pub id: String,
+    pub username: String,
+    pub created_at: NaiveDateTime,
+    pub role: Role,
+    pub server_groups: Vec<String>,
+}
+
+impl From<User> for UserResponse {
+    fn from(value: User) -> Self {
+        Self {
+            id: value.id,
+            username: value.username,
+            created_at: value.created_at,
+            role: value.role,
+            server_groups: value.server_groups,
+        }
+    }
+}
@@ -10,6 +10,8 @@ use tower_http::cors::{Any, CorsLayer};
 
 pub mod components;
 const T_OUT: Duration = Duration::from_secs(10);
+/// the longest a handler waits for an agent or a command, so it can still answer before [`T_OUT`]
+pub const HANDLER_TIMEOUT: Duration = Duration::from_secs(8);
 pub async fn run(state:SharedState) -> Res {
     
     let helmet = build_helmet();
@@ -0,0 +1,104 @@
+use crate::libs::app_config::AppConfig;
+use eyre::eyre;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Component, Path};
+
+const MANIFEST_NAME: &str = "manifest.json";
+
+/// prebuilt agents that replace the build on the server, e.g. for hosts without internet access.
+///
+/// a bundle is a directory with the binaries and a `manifest.json`:
+/// `{"agents":[{"target":"x86_64-unknown-linux-musl","file":"agent-x86_64","sha256":".."}]}`
+#[derive(Default)]
+pub struct AgentBundle {
+    /// target triple -> agent
+    agents: HashMap<String, BundledAgent>,
+}
+
+pub struct BundledAgent {
+    pub sha256: String,
+    pub binary: Vec<u8>,
+}
+
+#[derive(Deserialize)]
+struct Manifest {
+    agents: Vec<ManifestEntry>,
+}
+
+#[derive(Deserialize)]
+struct ManifestEntry {
+    target: String,
+    /// relative to the manifest
+    file: String,
+    sha256: String,
+}
+
+/// the directory is filled by the release build, without it the embedded bundle is empty
+/// and loading it fails with a missing manifest
+#[cfg(feature = "embedded-agent-bundle")]
+#[derive(rust_embed::Embed)]
+#[folder = "../agent-bundle/"]
+#[allow_missing = true]
+struct EmbeddedBundle;
+
+impl AgentBundle {
+    /// the bundle of `--agent-bundle-dir`, or the embedded one if the server was built with it
+    pub fn configured(config: &AppConfig) -> eyre::Result<Option<Self>> {
+        if !config.agent_bundle_dir.is_empty() {
+            let dir = Path::new(&config.agent_bundle_dir);
+            return Self::load(|file| Ok(std::fs::read(dir.join(file))?)).map(Some);
+        }
+        Self::embedded()
+    }
+
+    #[cfg(feature = "embedded-agent-bundle")]
+    fn embedded() -> eyre::Result<Option<Self>> {
+        Self::load(|file| {
+            EmbeddedBundle::get(file)
+                .map(|f| f.data.into_owned())
+                .ok_or(eyre!("{file} is not in the embedded bundle"))
+        })
+        .map(Some)
+    }
+
+    #[cfg(not(feature = "embedded-agent-bundle"))]
+    fn embedded() -> eyre::Result<Option<Self>> {
+        Ok(None)
+    }
+
+    /// every binary is checked against the checksum of the manifest
+    fn load(read: impl Fn(&str) -> eyre::Result<Vec<u8>>) -> eyre::Result<Self> {
+        let manifest = serde_json::from_slice::<Manifest>(&read(MANIFEST_NAME)?)?;
+        let mut agents = HashMap::new();
+        for entry in manifest.agents {
+            let mut components = Path::new(&entry.file).components();
+            if !matches!(
+                (components.next(), components.next()),
+                (Some(Component::Normal(_)), None)
+            ) {
+                return Err(eyre!("{} must be a file next to the manifest", entry.file));
+            }
+            let binary = read(&entry.file)?;
+            let sha256 = hex::encode(Sha256::digest(&binary));
+            if !sha256.eq_ignore_ascii_case(&entry.sha256) {
+                return Err(eyre!(
+                    "the checksum of {} does not match the manifest (expected {}, got {sha256})",
+                    entry.file,
+                    entry.sha256
+                ));
+            }
+            agents.insert(entry.target, BundledAgent { sha256, binary });
+        }
+        Ok(Self { agents })
+    }
+
+    pub fn get(&self, target: &str) -> Option<&BundledAgent> {
+        self.agents.get(target)
+    }
+
+    pub fn targets(&self) -> Vec<&str> {
+        self.agents.keys().map(|t| t.as_str()).collect()
+    }
+}
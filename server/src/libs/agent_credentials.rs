@@ -0,0 +1,175 @@
+use crate::api;
+use crate::libs::app_config::AppConfig;
+use crate::libs::db_driver::DbDriver;
+use crate::libs::shared_state::SharedState;
+use crate::libs::{self, TokenClaims};
+use crate::models::agent_credential::{AgentCredential, RevokedAgentToken};
+use crate::prelude::Res;
+use agent_shared::{AgentRequest, AgentResponse};
+use chrono::Utc;
+use eyre::eyre;
+use jsonwebtoken::{decode, DecodingKey, Validation};
+use std::sync::LazyLock;
+
+/// the agent tokens don't expire, they are revoked instead
+static V: LazyLock<Validation> = LazyLock::new(|| {
+    let mut v = Validation::default();
+    v.validate_exp = false;
+    v
+});
+
+/// issue a new token for the agent of the server, every previous token of the agent is revoked
+pub fn issue(
+    db_driver: &DbDriver,
+    secret: &str,
+    server_id: &str,
+    cert_fingerprint: Option<String>,
+    reason: &str,
+) -> eyre::Result<String> {
+    // the agent might still run with a token from before the credentials existed
+    if db_driver.get_agent_credential(server_id)?.is_none() {
+        db_driver.add_revoked_agent_token(RevokedAgentToken {
+            jti: legacy_jti(server_id),
+            server_id: server_id.to_owned(),
+            revoked_at: Utc::now().naive_utc(),
+            reason: reason.to_owned(),
+        })?;
+    }
+
+    let jti = cuid2::create_id();
+    db_driver.replace_agent_credential(
+        AgentCredential {
+            server_id: server_id.to_owned(),
+            jti: jti.clone(),
+            issued_at: Utc::now().naive_utc(),
+            pending_jti: None,
+            cert_fingerprint,
+        },
+        reason,
+    )?;
+    Ok(libs::create_jwt_token(secret, &server_id.to_owned(), &jti))
+}
+
+/// push a new token to the connected agent, the current token stays valid until the agent confirms it
+pub async fn rotate(state: &SharedState, server_id: &str) -> Res {
+    let db_driver = &state.db_driver;
+    if db_driver.get_agent_credential(server_id)?.is_none() {
+        // the agent uses a token from before the credentials existed, track it as the current one
+        db_driver.replace_agent_credential(
+            AgentCredential {
+                server_id: server_id.to_owned(),
+                jti: legacy_jti(server_id),
+                issued_at: Utc::now().naive_utc(),
+                pending_jti: None,
+                cert_fingerprint: None,
+            },
+            "rotated",
+        )?;
+    }
+
+    let jti = cuid2::create_id();
+    let token = libs::create_jwt_token(&state.app_config.agent_secret, &server_id.to_owned(), &jti);
+    db_driver.set_pending_agent_token(server_id, Some(jti.clone()))?;
+
+    let response = state
+        .agent_hub
+        .request(
+            server_id,
+            AgentRequest::RotateToken { token },
+            api::HANDLER_TIMEOUT,
+        )
+        .await;
+    match response {
+        Ok(AgentResponse::Done) => confirm(state, server_id, &jti),
+        Ok(response) => {
+            db_driver.set_pending_agent_token(server_id, None)?;
+            Err(eyre!("the agent rejected the new token: {response:?}"))
+        }
+        Err(e) => {
+            // the agent might have stored the token anyway, it is confirmed once it is used
+            log::warn!("the rotation of the token of {server_id} is not confirmed: {e}");
+            Err(e)
+        }
+    }
+}
+
+/// validate the token of an agent message and return its claims
+pub fn authenticate(state: &SharedState, token: &Option<String>) -> Option<TokenClaims> {
+    let token = token.as_ref()?;
+    let config: &AppConfig = &state.app_config;
+    let result = match decode_token(&config.agent_secret, token) {
+        Some(claims) => authorize(state, claims),
+        // tokens from before the credentials existed are signed with the password,
+        // anyone who knows it could sign one, so they are only accepted when explicitly allowed
+        None if config.allow_legacy_agent_tokens => decode_token(&config.pwd, token)
+            .filter(|c| c.jti.is_empty())
+            .ok_or(eyre!("invalid token"))
+            .and_then(|claims| authorize(state, claims)),
+        None => Err(eyre!(
+            "invalid token, agents deployed before the agent credentials need --allow-legacy-agent-tokens until they are deployed again"
+        )),
+    };
+    result
+        .inspect_err(|e| log::error!("agent authentication failed: {e}"))
+        .ok()
+}
+
+fn authorize(state: &SharedState, mut claims: TokenClaims) -> eyre::Result<TokenClaims> {
+    let db_driver = &state.db_driver;
+    let legacy = claims.jti.is_empty();
+    if legacy {
+        claims.jti = legacy_jti(&claims.sub);
+    }
+    if db_driver.is_agent_token_revoked(&claims.jti)? {
+        return Err(eyre!("the token of {} is revoked", claims.sub));
+    }
+
+    match db_driver.get_agent_credential(&claims.sub)? {
+        Some(credential) if credential.jti.eq(&claims.jti) => Ok(claims),
+        // the agent already switched to the rotated token
+        Some(credential) if credential.pending_jti.as_ref() == Some(&claims.jti) => {
+            confirm(state, &claims.sub, &claims.jti)?;
+            Ok(claims)
+        }
+        Some(_) => Err(eyre!("the token of {} was replaced", claims.sub)),
+        None if legacy && db_driver.get_server_by_id(claims.sub.clone())?.is_some() => {
+            log::warn!(
+                "the agent of {} uses a legacy token signed with the password, rotate it or deploy the agent again, \
+                then drop --allow-legacy-agent-tokens",
+                claims.sub
+            );
+            Ok(claims)
+        }
+        None => Err(eyre!("{} has no agent credential", claims.sub)),
+    }
+}
+
+fn confirm(state: &SharedState, server_id: &str, jti: &str) -> Res {
+    log::info!("the agent of {server_id} switched to its new token");
+    // the client certificate is not rotated with the token
+    let cert_fingerprint = state
+        .db_driver
+        .get_agent_credential(server_id)?
+        .and_then(|c| c.cert_fingerprint);
+    state.db_driver.replace_agent_credential(
+        AgentCredential {
+            server_id: server_id.to_owned(),
+            jti: jti.to_owned(),
+            issued_at: Utc::now().naive_utc(),
+            pending_jti: None,
+            cert_fingerprint,
+        },
+        "rotated",
+    )
+}
+
+fn decode_token(secret: &str, token: &str) -> Option<TokenClaims> {
+    decode::<TokenClaims>(token, &DecodingKey::from_secret(secret.as_bytes()), &V)
+        .ok()
+        .map(|t| t.claims)
+}
+
+/// the id that stands for the token of an agent deployed before the credentials existed
+fn legacy_jti(server_id: &str) -> String {
+    format!("legacy:{server_id}")
+}
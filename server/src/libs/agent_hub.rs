@@ -0,0 +1,298 @@
+use crate::prelude::Res;
+use agent_shared::{AgentRequest, AgentResponse, ServerMessage, Signal};
+use chrono::{NaiveDateTime, TimeDelta, Utc};
+use eyre::eyre;
+use message_io::network::{Endpoint, SendStatus};
+use message_io::node::NodeHandler;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+/// the last-seen time of a server is written to the db at most once in this period
+const PERSIST_LAST_SEEN_EVERY: TimeDelta = TimeDelta::seconds(60);
+
+/// keeps track of the connected agents and correlates the requests sent to them with their responses
+#[derive(Clone, Default)]
+pub struct AgentHub {
+    inner: Arc<Mutex<AgentHubInner>>,
+}
+
+#[derive(Default)]
+struct AgentHubInner {
+    handler: Option<NodeHandler<Signal>>,
+    /// server_id -> session of its agent
+    sessions: HashMap<String, AgentSession>,
+    /// endpoint -> server_id, the endpoints that completed the handshake
+    endpoints: HashMap<Endpoint, String>,
+    pending: HashMap<u64, PendingRequest>,
+    next_request_id: u64,
+    /// the servers whose agent is being updated
+    updating: HashSet<String>,
+}
+
+/// held while the agent of the server is updated, see [`AgentHub::begin_update`]
+pub struct UpdateGuard {
+    hub: AgentHub,
+    server_id: String,
+}
+
+impl Drop for UpdateGuard {
+    fn drop(&mut self) {
+        let mut l = self.hub.inner.lock().unwrap();
+        l.updating.remove(&self.server_id);
+    }
+}
+
+/// a request waiting for its response, only the connection it was sent to can answer it
+struct PendingRequest {
+    server_id: String,
+    endpoint: Endpoint,
+    sender: oneshot::Sender<AgentResponse>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct AgentSession {
+    #[serde(skip)]
+    pub endpoint: Endpoint,
+    pub connected_since: NaiveDateTime,
+    pub last_message: NaiveDateTime,
+    pub agent_version: Option<u16>,
+    /// announced in the handshake, empty for the agents that predate it
+    pub capabilities: Vec<String>,
+    /// the wire format the agent speaks, see [`agent_shared::PROTOCOL_VERSION`]
+    pub protocol_version: u16,
+    /// the capabilities known to both sides, only enforced from protocol version 2 on
+    pub features: Vec<String>,
+    #[serde(skip)]
+    last_persisted: Option<NaiveDateTime>,
+}
+
+impl AgentHub {
+    /// called by the sub-server io once its listener is up
+    pub fn attach(&self, handler: NodeHandler<Signal>) {
+        self.inner.lock().unwrap().handler = Some(handler);
+    }
+
+    /// record a message of the agent, returns true if its last-seen time should be persisted
+    pub fn bind(&self, server_id: &str, endpoint: Endpoint, protocol_version: u16) -> bool {
+        let now = Utc::now().naive_utc();
+        let mut l = self.inner.lock().unwrap();
+        // the agent reconnected, the old connection is no longer trusted
+        let previous = l
+            .sessions
+            .get(server_id)
+            .map(|s| s.endpoint)
+            .filter(|e| !e.eq(&endpoint));
+        if let Some(previous) = previous {
+            l.endpoints.remove(&previous);
+            l.sessions.remove(server_id);
+        }
+        l.endpoints.insert(endpoint, server_id.to_owned());
+
+        let session = l
+            .sessions
+            .entry(server_id.to_owned())
+            .or_insert_with(|| AgentSession::new(endpoint, now));
+        session.last_message = now;
+        session.protocol_version = protocol_version;
+
+        let persist = session
+            .last_persisted
+            .is_none_or(|p| now - p >= PERSIST_LAST_SEEN_EVERY);
+        if persist {
+            session.last_persisted = Some(now);
+        }
+        persist
+    }
+
+    /// returns the negotiated features
+    pub fn set_agent_info(
+        &self,
+        server_id: &str,
+        version: u16,
+        capabilities: Vec<String>,
+    ) -> Vec<String> {
+        let mut l = self.inner.lock().unwrap();
+        let Some(session) = l.sessions.get_mut(server_id) else {
+            return vec![];
+        };
+        session.agent_version = Some(version);
+        session.features = agent_shared::negotiate(&capabilities);
+        session.capabilities = capabilities;
+        session.features.clone()
+    }
+
+    /// send the message in the format the agent of the server speaks
+    pub fn send(&self, server_id: &str, message: &ServerMessage) -> Res {
+        let l = self.inner.lock().unwrap();
+        let handler = l
+            .handler
+            .as_ref()
+            .ok_or(eyre!("sub-server io is not running"))?;
+        let session = l
+            .sessions
+            .get(server_id)
+            .ok_or(eyre!("the agent of {server_id} is not connected"))?;
+        let data = agent_shared::encode_for(message, session.protocol_version)?;
+        let status = handler.network().send(session.endpoint, &data);
+        if status != SendStatus::Sent {
+            return Err(eyre!("failed to send to {server_id}: {status:?}"));
+        }
+        Ok(())
+    }
+
+    /// the server whose agent completed the handshake on the endpoint
+    pub fn server_of(&self, endpoint: &Endpoint) -> Option<String> {
+        self.inner.lock().unwrap().endpoints.get(endpoint).cloned()
+    }
+
+    /// returns the id of the server whose agent was connected through the endpoint
+    pub fn unbind(&self, endpoint: Endpoint) -> Option<String> {
+        let mut l = self.inner.lock().unwrap();
+        let server_id = l.endpoints.remove(&endpoint)?;
+        // the session might already belong to a newer connection of the agent
+        if l.sessions
+            .get(&server_id)
+            .is_some_and(|s| s.endpoint.eq(&endpoint))
+        {
+            l.sessions.remove(&server_id);
+        }
+        Some(server_id)
+    }
+
+    /// drop the connection of the agent, e.g. after its token was revoked
+    pub fn disconnect(&self, server_id: &str) {
+        let mut l = self.inner.lock().unwrap();
+        let Some(session) = l.sessions.remove(server_id) else {
+            return;
+        };
+        l.endpoints.remove(&session.endpoint);
+        // removing the resource locally doesn't emit a disconnected event, so the session is dropped here
+        if let Some(handler) = &l.handler {
+            handler.network().remove(session.endpoint.resource_id());
+        }
+    }
+
+    /// returns none if an update of the agent is already running
+    pub fn begin_update(&self, server_id: &str) -> Option<UpdateGuard> {
+        let mut l = self.inner.lock().unwrap();
+        if !l.updating.insert(server_id.to_owned()) {
+            return None;
+        }
+        Some(UpdateGuard {
+            hub: self.clone(),
+            server_id: server_id.to_owned(),
+        })
+    }
+
+    pub fn session(&self, server_id: &str) -> Option<AgentSession> {
+        self.inner.lock().unwrap().sessions.get(server_id).cloned()
+    }
+
+    /// hand the response of the agent to whoever is waiting for it.
+    ///
+    /// only the agent the request was sent to, on the same connection, can complete it
+    pub fn complete_from(
+        &self,
+        server_id: &str,
+        endpoint: Endpoint,
+        id: u64,
+        response: AgentResponse,
+    ) {
+        let mut l = self.inner.lock().unwrap();
+        let Some(pending) = l.pending.get(&id) else {
+            log::info!("received a response for an unknown request ({id})");
+            return;
+        };
+        let owned = pending.server_id.eq(server_id) && pending.endpoint.eq(&endpoint);
+        if !owned {
+            log::warn!("{server_id} sent a response for a request it doesn't own ({id})");
+            return;
+        }
+        if let Some(pending) = l.pending.remove(&id) {
+            let _ = pending.sender.send(response);
+        }
+    }
+
+    /// send the request to the agent of the server and wait for its response
+    pub async fn request(
+        &self,
+        server_id: &str,
+        request: AgentRequest,
+        timeout: Duration,
+    ) -> eyre::Result<AgentResponse> {
+        let (tx, rx) = oneshot::channel();
+        let id = {
+            let mut l = self.inner.lock().unwrap();
+            let handler = l
+                .handler
+                .clone()
+                .ok_or(eyre!("sub-server io is not running"))?;
+            let session = l
+                .sessions
+                .get(server_id)
+                .ok_or(eyre!("the agent of {server_id} is not connected"))?;
+            let (endpoint, protocol_version) = (session.endpoint, session.protocol_version);
+            // older agents don't announce their features, their requests are sent as is
+            let feature = request.feature();
+            if protocol_version >= 2 && !session.features.iter().any(|f| f.eq(feature)) {
+                return Err(eyre!(
+                    "the agent of {server_id} does not support `{feature}`, redeploy it to update"
+                ));
+            }
+
+            l.next_request_id += 1;
+            let id = l.next_request_id;
+            l.pending.insert(
+                id,
+                PendingRequest {
+                    server_id: server_id.to_owned(),
+                    endpoint,
+                    sender: tx,
+                },
+            );
+
+            let data = agent_shared::encode_for(
+                &ServerMessage::Request { id, request },
+                protocol_version,
+            )?;
+            let status = handler.network().send(endpoint, &data);
+            if status != SendStatus::Sent {
+                l.pending.remove(&id);
+                return Err(eyre!(
+                    "failed to send the request to {server_id}: {status:?}"
+                ));
+            }
+            id
+        };
+
+        match tokio::time::timeout(timeout, rx).await {
+            Ok(Ok(response)) => Ok(response),
+            Ok(Err(_)) => Err(eyre!("the request was dropped")),
+            Err(_) => {
+                self.inner.lock().unwrap().pending.remove(&id);
+                Err(eyre!(
+                    "the agent did not respond within {} seconds",
+                    timeout.as_secs()
+                ))
+            }
+        }
+    }
+}
+
+impl AgentSession {
+    fn new(endpoint: Endpoint, now: NaiveDateTime) -> Self {
+        Self {
+            endpoint,
+            connected_since: now,
+            last_message: now,
+            agent_version: None,
+            capabilities: vec![],
+            protocol_version: 1,
+            features: vec![],
+            last_persisted: None,
+        }
+    }
+}
@@ -0,0 +1,146 @@
+use crate::libs::secret_box;
+use crate::prelude::{Res, DATA_DIR_PATH};
+use rcgen::{
+    BasicConstraints, Certificate, CertificateParams, DistinguishedName, DnType,
+    ExtendedKeyUsagePurpose, IsCa, KeyPair, KeyUsagePurpose,
+};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use std::sync::Arc;
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{RootCertStore, ServerConfig};
+
+/// the name the agents expect in the certificate of the server, they connect by ip
+pub const SERVER_NAME: &str = "managers-server";
+const CA_COMMON_NAME: &str = "managers_server agent ca";
+
+/// the certificate authority that signs the certificates of the agent channel.
+///
+/// it is generated on the first start under `DATA_DIR_PATH/agent_ca`,
+/// each agent gets a client certificate issued to its server id when it is deployed
+#[derive(Clone)]
+pub struct AgentPki {
+    inner: Arc<AgentPkiInner>,
+}
+
+struct AgentPkiInner {
+    ca_key: KeyPair,
+    /// only used for signing, the subject and the key are the same as the stored certificate
+    ca_cert: Certificate,
+    ca_pem: String,
+}
+
+/// the credentials of a single agent, in PEM format
+pub struct AgentCertificate {
+    pub cert_pem: String,
+    pub key_pem: String,
+    /// see [`fingerprint`]
+    pub fingerprint: String,
+}
+
+impl AgentPki {
+    pub async fn load_or_create() -> eyre::Result<Self> {
+        let dir = DATA_DIR_PATH.join("agent_ca");
+        let (key_path, cert_path) = (dir.join("ca.key.pem"), dir.join("ca.cert.pem"));
+
+        let (ca_key, ca_pem) = if tokio::fs::try_exists(&key_path).await? {
+            let key = KeyPair::from_pem(&tokio::fs::read_to_string(&key_path).await?)?;
+            (key, tokio::fs::read_to_string(&cert_path).await?)
+        } else {
+            log::info!("generating the certificate authority of the agents -> {dir:?}");
+            tokio::fs::create_dir_all(&dir).await?;
+            let key = KeyPair::generate()?;
+            let cert = ca_params()?.self_signed(&key)?;
+            write_private(&key_path, &key.serialize_pem()).await?;
+            tokio::fs::write(&cert_path, cert.pem()).await?;
+            (key, cert.pem())
+        };
+        let ca_cert = ca_params()?.self_signed(&ca_key)?;
+
+        Ok(Self {
+            inner: Arc::new(AgentPkiInner {
+                ca_key,
+                ca_cert,
+                ca_pem,
+            }),
+        })
+    }
+
+    pub fn ca_pem(&self) -> &str {
+        &self.inner.ca_pem
+    }
+
+    /// a client certificate whose common name is the server id
+    pub fn issue_agent_cert(&self, server_id: &str) -> eyre::Result<AgentCertificate> {
+        let key = KeyPair::generate()?;
+        let mut params = CertificateParams::new(Vec::<String>::new())?;
+        params.distinguished_name = DistinguishedName::new();
+        params
+            .distinguished_name
+            .push(DnType::CommonName, server_id);
+        params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+        let cert = params.signed_by(&key, &self.inner.ca_cert, &self.inner.ca_key)?;
+
+        Ok(AgentCertificate {
+            fingerprint: fingerprint(cert.der()),
+            cert_pem: cert.pem(),
+            key_pem: key.serialize_pem(),
+        })
+    }
+
+    /// tls config of the agent listener, every client has to present a certificate issued by the ca.
+    ///
+    /// the certificate of the server is only kept in memory, a new one is generated on every start
+    pub fn server_config(&self) -> eyre::Result<ServerConfig> {
+        let provider = Arc::new(ring::default_provider());
+        let mut roots = RootCertStore::empty();
+        roots.add(CertificateDer::from_pem_slice(
+            self.inner.ca_pem.as_bytes(),
+        )?)?;
+        let verifier =
+            WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+                .build()?;
+
+        let key = KeyPair::generate()?;
+        let mut params = CertificateParams::new(vec![SERVER_NAME.to_owned()])?;
+        params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+        let cert = params.signed_by(&key, &self.inner.ca_cert, &self.inner.ca_key)?;
+
+        let config = ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()?
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(
+                vec![cert.der().clone()],
+                PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key.serialize_der())),
+            )?;
+        Ok(config)
+    }
+}
+
+/// hex encoded sha256 of the DER certificate
+pub fn fingerprint(der: &[u8]) -> String {
+    hex::encode(Sha256::digest(der))
+}
+
+fn ca_params() -> eyre::Result<CertificateParams> {
+    let mut params = CertificateParams::new(Vec::<String>::new())?;
+    params.distinguished_name = DistinguishedName::new();
+    params
+        .distinguished_name
+        .push(DnType::CommonName, CA_COMMON_NAME);
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    params.key_usages = vec![
+        KeyUsagePurpose::KeyCertSign,
+        KeyUsagePurpose::CrlSign,
+        KeyUsagePurpose::DigitalSignature,
+    ];
+    params.not_after = rcgen::date_time_ymd(2100, 1, 1);
+    Ok(params)
+}
+
+async fn write_private(path: &Path, content: &str) -> Res {
+    secret_box::write_owner_only(path, content.as_bytes()).await
+}
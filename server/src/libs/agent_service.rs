@@ -1,15 +1,21 @@
-use crate::libs;
+use crate::libs::agent_bundle::AgentBundle;
+use crate::libs::agent_credentials;
+use crate::libs::agent_pki::{AgentCertificate, AgentPki};
 use crate::libs::app_config::AppConfigRef;
-use crate::libs::ssh_session;
+use crate::libs::db_driver::DbDriver;
+use crate::libs::deploy_jobs::JobLog;
+use crate::libs::ssh_session::{self, SshSession};
 use crate::models::server::Server;
 use crate::prelude::{Res, DATA_DIR_PATH};
 use eyre::eyre;
 use itertools::Itertools;
 use rust_embed::Embed;
 use std::borrow::Cow;
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::sync::Mutex;
 use tokio::time::Instant;
@@ -17,6 +23,17 @@ use tokio::time::Instant;
 /// default path of the agent on the sub server
 const SS_AGENT_PATH: &str = "/usr/bin/managers_agent";
 const AGENT_UNIT_NAME: &str = "managers_agent";
+const SS_AGENT_DIR: &str = "/usr/share/managers_agent";
+const SS_AGENT_CA_PATH: &str = "/usr/share/managers_agent/ca.cert.pem";
+const SS_AGENT_CERT_PATH: &str = "/usr/share/managers_agent/agent.cert.pem";
+const SS_AGENT_KEY_PATH: &str = "/usr/share/managers_agent/agent.key.pem";
+const SS_AGENT_UNIT_PATH: &str = "/etc/systemd/system/managers_agent.service";
+/// the timer of the agent that restores the previous binary after a failed self-update
+const SS_AGENT_ROLLBACK_UNIT_NAME: &str = "managers_agent_rollback";
+/// the target the agent is built for on startup, the other targets are built on first use
+const DEFAULT_TARGET: &str = "x86_64-unknown-linux-musl";
+/// the init and restart of the agent on the sub server
+const SS_COMMAND_TIMEOUT: Duration = Duration::from_secs(60);
 #[derive(Embed)]
 #[folder = "../agent/"]
 #[include= "src/*"]
@@ -35,22 +52,45 @@ struct AgentSharedAsset;
 pub struct AgentService {
     inner: Arc<Mutex<AgentServiceInner>>,
     app_config: AppConfigRef,
+    db_driver: DbDriver,
+    agent_pki: AgentPki,
+    /// the prebuilt agents, nothing is built on the server when set
+    bundle: Option<Arc<AgentBundle>>,
 }
 #[derive(Default)]
 struct AgentServiceInner {
     last_agent_src_hash: String,
-    agent_bin_path: String,
+    /// target triple -> the agent built from the current sources
+    agent_bin_paths: HashMap<String, String>,
     last_shared_lib_src_hash: String
 }
 
 impl AgentService {
-    pub async fn new(app_config: AppConfigRef) -> Self {
+    pub async fn new(app_config: AppConfigRef, db_driver: DbDriver, agent_pki: AgentPki) -> Self {
+        let bundle = AgentBundle::configured(&app_config).unwrap_or_else(|e| {
+            // don't fall back to building, the bundle is used where nothing can be downloaded
+            log::error!("failed to load the agent bundle, the agents can't be deployed: {e}");
+            Some(AgentBundle::default())
+        });
         let slf = Self {
             app_config,
+            db_driver,
+            agent_pki,
+            bundle: bundle.map(Arc::new),
             inner: Arc::new(Mutex::new(AgentServiceInner::default())),
         };
         log::info!("initializing agents");
-        slf.build_agent().await.unwrap();
+        match &slf.bundle {
+            Some(bundle) => log::info!(
+                "using the prebuilt agents for [{}]",
+                bundle.targets().join(", ")
+            ),
+            None => {
+                if let Err(e) = slf.build_agent().await {
+                    log::error!("failed to build the agent, it can't be deployed: {e}");
+                }
+            }
+        }
         slf
     }
 
@@ -61,89 +101,234 @@ impl AgentService {
     /// a directory will be created in `/usr/share/managers_agent/` to write the `agent.lock` inside it
     ///
     /// the lock will be later used to compare src hashes and avoid rebuilding the same agent
-    pub async fn upload_agent(&self, server: &Server) -> Res {
-        let l = self.inner.lock().await;
-        let agent_path = l.agent_bin_path.clone();
-        let last_src_hash = l.last_agent_src_hash.clone();
-        drop(l);
-        if !tokio::fs::try_exists(&agent_path).await? {
-            return Err(eyre!("agent did not build yet!"));
+    ///
+    /// returns false if the same build is already on the server, unless the job is forced
+    pub async fn upload_agent(&self, server: &Server, job: &JobLog) -> eyre::Result<bool> {
+        job.log(format!("connecting to {}", server.ip));
+        let mut ssh = ssh_session::connect(server, &self.db_driver).await?;
+
+        let arch = ssh.call_capture_output("uname -m").await?.trim().to_owned();
+        let target = target_triple(&arch)?;
+        self.db_driver.set_server_arch(&server.id, &arch)?;
+        job.log(format!(
+            "{} runs on {arch}, using the {target} build",
+            server.ip
+        ));
+        if !self.is_built(target).await {
+            // the build can take longer than the session stays open
+            ssh.close().await?;
+            job.log(format!("building the agent for {target}"));
+            self.build_target(target).await?;
+            ssh = ssh_session::connect(server, &self.db_driver).await?;
         }
-        let agent_binary = tokio::fs::read(agent_path).await?;
-
-        let mut ssh = ssh_session::connect(server).await?;
-
-        log::info!("agent path: {SS_AGENT_PATH}");
+        let agent_binary = self.agent_binary(target).await?;
+        // the bundled agents are compared by their checksum
+        let last_src_hash = match &self.bundle {
+            Some(bundle) => bundle
+                .get(target)
+                .map(|a| a.sha256.clone())
+                .unwrap_or_default(),
+            None => self.inner.lock().await.last_agent_src_hash.clone(),
+        };
 
         let sftp = ssh.get_sftp().await?;
 
-        // create managers dir
-        let managers_agent_dir = PathBuf::from_str("/usr/share/managers_agent")?;
-        let managers_agent_dir_str = managers_agent_dir.to_str().unwrap().to_owned();
-
-        if !sftp.try_exists(&managers_agent_dir_str).await? {
-            sftp.create_dir(&managers_agent_dir_str).await?;
-        }
         //agent lock file
-        let agent_lock_file = managers_agent_dir
+        let agent_lock_file = PathBuf::from_str(SS_AGENT_DIR)?
             .join("agent.lock")
             .to_str()
             .unwrap()
             .to_owned();
 
-        if sftp.try_exists(SS_AGENT_PATH).await? {
-            log::info!("an old version of the agent already exists, checking for hashes");
+        let deployed =
+            sftp.try_exists(SS_AGENT_PATH).await? && sftp.try_exists(&agent_lock_file).await?;
+        if deployed && !job.force() {
+            job.log("an old version of the agent already exists, checking for hashes");
             //check lock file, if the src is not changed, do not replace the agent
             let mut file = sftp.open(&agent_lock_file).await?;
             let mut hash = String::default();
             file.read_to_string(&mut hash).await?;
             if hash.eq(&last_src_hash) {
-                log::error!("agent already exists on machine {}", server.ip);
-                return Ok(());
+                job.log(format!("the same build already exists on {}", server.ip));
+                sftp.close().await?;
+                ssh.close().await?;
+                return Ok(false);
             }
         }
+        sftp.close().await?;
 
-        log::info!("sending agent to {}", server.ip);
-        let mut file = sftp.create(SS_AGENT_PATH).await?;
-        file.write_all(&agent_binary).await?;
-        file.flush().await?;
-        file.shutdown().await?;
-
-        ssh.call_capture_output(&format!("chmod +x {SS_AGENT_PATH}"))
-            .await?;
+        job.log(format!(
+            "uploading the agent ({} bytes) to {SS_AGENT_PATH}",
+            agent_binary.len()
+        ));
+        let files = [
+            (SS_AGENT_PATH, agent_binary.as_slice(), "755"),
+            (agent_lock_file.as_str(), last_src_hash.as_bytes(), "644"),
+        ];
+        Self::install_files(&mut ssh, job, &files).await?;
 
-        let mut file = sftp.create(agent_lock_file).await?;
+        ssh.close().await?;
+        job.log("the agent is uploaded");
+        Ok(true)
+    }
 
-        file.write_all(last_src_hash.as_bytes()).await?;
-        file.flush().await?;
-        file.shutdown().await?;
+    /// the binary of the last agent build for the target, it is built if needed
+    pub async fn agent_binary(&self, target: &str) -> eyre::Result<Vec<u8>> {
+        if let Some(bundle) = &self.bundle {
+            return bundle
+                .get(target)
+                .map(|a| a.binary.clone())
+                .ok_or(eyre!("the agent bundle has no build for {target}"));
+        }
+        let agent_path = self.inner.lock().await.agent_bin_paths.get(target).cloned();
+        let agent_path = match agent_path {
+            Some(agent_path) => PathBuf::from(agent_path),
+            None => self.build_target(target).await?,
+        };
+        if !tokio::fs::try_exists(&agent_path).await? {
+            return Err(eyre!("agent did not build yet!"));
+        }
+        Ok(tokio::fs::read(agent_path).await?)
+    }
 
-        sftp.close().await?;
-        ssh.close().await?;
-        log::info!("agent sent");
-        Ok(())
+    /// the bundled agents are never built
+    async fn is_built(&self, target: &str) -> bool {
+        self.bundle.is_some() || self.inner.lock().await.agent_bin_paths.contains_key(target)
     }
 
-    pub async fn init_agent(&self, server: &Server) -> Res {
-        let mut ssh = ssh_session::connect(server).await?;
+    /// build the agent from the current sources for another target,
+    /// cargo skips the compilation if the output of a previous run is up to date
+    async fn build_target(&self, target: &str) -> eyre::Result<PathBuf> {
+        self.install_toolchain(target).await?;
+        let src_path = DATA_DIR_PATH.join("agent_src").join("Cargo.toml");
+        self.compile_agent(src_path.to_str().unwrap(), target).await
+    }
 
-        let token = libs::create_jwt_token(&self.app_config.pwd, &server.id);
+    pub async fn init_agent(&self, server: &Server, job: &JobLog) -> Res {
+        let mut ssh = ssh_session::connect(server, &self.db_driver).await?;
+
+        job.log("uploading the certificates of the agent");
+        let cert = self.agent_pki.issue_agent_cert(&server.id)?;
+        self.upload_certificates(&mut ssh, job, &cert).await?;
+
+        // a new deployment gets a new token, the one of the previous deployment is revoked
+        let token = agent_credentials::issue(
+            &self.db_driver,
+            &self.app_config.agent_secret,
+            &server.id,
+            Some(cert.fingerprint),
+            "redeployed",
+        )?;
         let ip = public_ip::addr()
             .await
             .ok_or(eyre!("failed to find the public ip"))?;
         //use 3939 by default
-        ssh.call_with_stdout(&format!("dash -c '{SS_AGENT_PATH} init {token} {ip}:3939'"))
-            .await?;
+        job.log(format!("initializing the agent with {ip}:3939"));
+        let init = format!("dash -c '{SS_AGENT_PATH} init {token} {ip}:3939'");
+        Self::run_logged(&mut ssh, job, "init", &init).await?;
 
-        ssh.call_with_stdout(&format!("systemctl restart {AGENT_UNIT_NAME}"))
-            .await?;
+        job.log(format!("restarting {AGENT_UNIT_NAME}"));
+        let restart = format!("systemctl restart {AGENT_UNIT_NAME}");
+        Self::run_logged(&mut ssh, job, "restart", &restart).await?;
+
+        ssh.close().await?;
+        Ok(())
+    }
+
+    /// stop the agent and remove everything its deployments left on the server
+    pub async fn uninstall_agent(&self, server: &Server, job: &JobLog) -> Res {
+        job.log(format!("connecting to {}", server.ip));
+        let mut ssh = ssh_session::connect(server, &self.db_driver).await?;
+
+        // the units might not exist if a deployment failed halfway, only the removal has to succeed
+        job.log(format!("stopping and removing {AGENT_UNIT_NAME}"));
+        let uninstall = format!(
+            "sh -c 'systemctl disable --now {AGENT_UNIT_NAME}; \
+            systemctl stop {SS_AGENT_ROLLBACK_UNIT_NAME}.timer; \
+            rm -f {SS_AGENT_UNIT_PATH} {SS_AGENT_PATH} {SS_AGENT_PATH}.update {SS_AGENT_PATH}.previous \
+            && rm -rf {SS_AGENT_DIR} \
+            && systemctl daemon-reload'"
+        );
+        Self::run_logged(&mut ssh, job, "uninstall", &uninstall).await?;
 
         ssh.close().await?;
         Ok(())
     }
 
+    /// run the command as root and add its output to the job, `name` is logged instead of the command
+    /// since it might contain secrets
+    async fn run_logged(ssh: &mut SshSession, job: &JobLog, name: &str, command: &str) -> Res {
+        let output = ssh.exec_privileged(command, SS_COMMAND_TIMEOUT).await?;
+        for output in [&output.stdout, &output.stderr] {
+            if !output.trim().is_empty() {
+                job.log(output.trim());
+            }
+        }
+        if output.timed_out {
+            return Err(eyre!(
+                "{name} did not finish within {} seconds",
+                SS_COMMAND_TIMEOUT.as_secs()
+            ));
+        }
+        match output.exit_code {
+            Some(0) => Ok(()),
+            code => Err(eyre!("{name} failed with exit code {code:?}")),
+        }
+    }
+
+    /// the client certificate of the agent and the ca of the server, used for the mutual tls of the agent channel
+    async fn upload_certificates(
+        &self,
+        ssh: &mut SshSession,
+        job: &JobLog,
+        cert: &AgentCertificate,
+    ) -> Res {
+        let files = [
+            (SS_AGENT_CA_PATH, self.agent_pki.ca_pem().as_bytes(), "644"),
+            (SS_AGENT_CERT_PATH, cert.cert_pem.as_bytes(), "644"),
+            (SS_AGENT_KEY_PATH, cert.key_pem.as_bytes(), "600"),
+        ];
+        Self::install_files(ssh, job, &files).await
+    }
+
+    /// `(path, content, mode)`, the files are uploaded to a temporary directory as the ssh user
+    /// and moved in place with `install` as root, the parent directories are created if needed
+    async fn install_files(
+        ssh: &mut SshSession,
+        job: &JobLog,
+        files: &[(&str, &[u8], &str)],
+    ) -> Res {
+        let tmp_dir = ssh
+            .call_capture_output("mktemp -d")
+            .await?
+            .trim()
+            .to_owned();
+        if tmp_dir.is_empty() {
+            return Err(eyre!("failed to create a temporary directory"));
+        }
+
+        let sftp = ssh.get_sftp().await?;
+        let mut install = vec![];
+        for (i, (path, content, mode)) in files.iter().enumerate() {
+            let tmp_path = format!("{tmp_dir}/{i}");
+            let mut file = sftp.create(&tmp_path).await?;
+            file.write_all(content).await?;
+            file.flush().await?;
+            file.shutdown().await?;
+            install.push(format!("install -D -m {mode} {tmp_path} {path}"));
+        }
+        sftp.close().await?;
+
+        let command = format!("sh -c '{}'", install.join(" && "));
+        let installed = Self::run_logged(ssh, job, "install", &command).await;
+        // the temporary files belong to the ssh user, no need for root
+        ssh.call_capture_output(&format!("rm -rf {tmp_dir}"))
+            .await?;
+        installed
+    }
+
     pub async fn build_agent(&self) -> Res {
-        self.install_toolchain().await?;
+        self.install_toolchain(DEFAULT_TARGET).await?;
 
         self.load_agent_lock().await?;
 
@@ -151,20 +336,26 @@ impl AgentService {
         let agent_cached = self.write_shared_lib_src().await?;
         if cached && agent_cached {
             let output_path = DATA_DIR_PATH.join("agent_output");
-            let agent_path = self.sync_agent_bin_path(&output_path).await?;
+            let agent_path = self
+                .sync_agent_bin_path(&output_path, DEFAULT_TARGET)
+                .await?;
             log::info!("skipping agent compilation -> {agent_path:?}");
             return Ok(());
         }
+        // the builds of the other targets are outdated
+        self.inner.lock().await.agent_bin_paths.clear();
         let src_path = PathBuf::from(src_path).join("Cargo.toml");
         let src_path = src_path.to_str().unwrap().to_string();
 
-        self.compile_agent(&src_path).await?;
+        if let Err(e) = self.compile_agent(&src_path, DEFAULT_TARGET).await {
+            log::error!("{e}");
+        }
 
         Ok(())
     }
 
-    async fn compile_agent(&self, src_path: &str) -> Res {
-        log::info!("compiling the agent...");
+    async fn compile_agent(&self, src_path: &str, target: &str) -> eyre::Result<PathBuf> {
+        log::info!("compiling the agent for {target}...");
         let output_path = DATA_DIR_PATH.join("agent_output");
         let output_path_str = output_path.to_str().unwrap();
 
@@ -175,7 +366,7 @@ impl AgentService {
             .join("bin")
             .join("cargo");
 
-        let mut command = std::process::Command::new(cargo.to_str().unwrap());
+        let mut command = tokio::process::Command::new(cargo.to_str().unwrap());
         let result = command.args([
             "build",
             "-q",
@@ -185,30 +376,59 @@ impl AgentService {
             "--target-dir",
             output_path_str,
             "--target",
-            "x86_64-unknown-linux-musl",
+            target,
         ]);
+        // the musl targets bring their own libc, so rust-lld can link them without a cross toolchain.
+        // crates with c code (e.g. ring) still need a c compiler for the target, see `CC_<target>`
+        if target != DEFAULT_TARGET {
+            // `cc` reads both spellings of the variable
+            let cc_vars = [
+                format!("CC_{target}"),
+                format!("CC_{}", target.replace('-', "_")),
+            ];
+            if cc_vars.iter().all(|v| std::env::var_os(v).is_none()) {
+                return Err(eyre!(
+                    "can't compile the agent for {target}: ring needs a c compiler for the target, \
+                     set {} (e.g. to a musl cross gcc or to `clang --target={target}`)",
+                    cc_vars[1]
+                ));
+            }
+            let linker = format!(
+                "CARGO_TARGET_{}_LINKER",
+                target.to_uppercase().replace('-', "_")
+            );
+            if std::env::var_os(&linker).is_none() {
+                result.env(linker, "rust-lld");
+            }
+        }
 
-        let result = result.output()?;
+        let result = result.output().await?;
 
-        if result.status.success() {
-            let agent_path = self.sync_agent_bin_path(&output_path).await?;
-            log::info!(
-                "agent compiled in {} seconds -> {agent_path:?}",
-                instant.elapsed().as_secs()
-            );
+        if !result.status.success() {
+            return Err(eyre!(
+                "failed to compile agent for {target} ({}):\n{}",
+                result.status.to_string(),
+                String::from_utf8_lossy(&result.stderr)
+            ));
+        }
+        let agent_path = self.sync_agent_bin_path(&output_path, target).await?;
+        log::info!(
+            "agent compiled in {} seconds -> {agent_path:?}",
+            instant.elapsed().as_secs()
+        );
 
+        if target == DEFAULT_TARGET {
             let l = self.inner.lock().await;
             let hash = l.last_agent_src_hash.clone();
             drop(l);
             tokio::fs::write(output_path.join("agent.lock"), hash.as_bytes()).await?;
-        } else {
-            log::error!("failed to compile agent ({}):\n{:?}", result.status.to_string(),String::from_utf8(result.stderr));
         }
-        Ok(())
+        Ok(agent_path)
     }
     
-    pub async fn install_toolchain(&self) -> Res {
-        log::info!("checking for toolchain");
+    /// install rustup and the target if they are missing
+    pub async fn install_toolchain(&self, target: &str) -> Res {
+        log::info!("checking for toolchain ({target})");
         let need_installation;
         let mut rustup_exists = false;
         //if the rustup is installed it will be here
@@ -218,15 +438,16 @@ impl AgentService {
             .join("bin")
             .join("rustup");
 
-        match std::process::Command::new(&installed_rustup_path)
+        match tokio::process::Command::new(&installed_rustup_path)
             .arg("show")
             .output()
+            .await
         {
             Ok(output) => {
                 if output.status.success() {
                     rustup_exists = true;
                     let output = String::from_utf8(output.stdout)?;
-                    need_installation = !output.contains("x86_64-unknown-linux-musl");
+                    need_installation = !output.contains(target);
                 } else {
                     need_installation = true;
                 }
@@ -252,7 +473,7 @@ impl AgentService {
 
             log::info!("downloading rustup -> {rustup_path:?}");
 
-            let status = std::process::Command::new("curl")
+            let status = tokio::process::Command::new("curl")
                 .args([
                     "--proto",
                     "=https",
@@ -262,7 +483,8 @@ impl AgentService {
                     "-o",
                     rustup_path.to_str().unwrap(),
                 ])
-                .status()?;
+                .status()
+                .await?;
 
             if !status.success() {
                 log::error!("failed to install rustup: {}", status.to_string());
@@ -270,29 +492,32 @@ impl AgentService {
             }
             log::info!("installing rustup");
 
-            std::process::Command::new("chmod")
+            tokio::process::Command::new("chmod")
                 .args(["+x", rustup_path.to_str().unwrap()])
-                .status()?;
+                .status()
+                .await?;
 
             //-y --default-host x86_64-unknown-linux-musl --no-modify-path --no-update-default-toolchain
-            let status = std::process::Command::new(rustup_path.to_str().unwrap())
+            let status = tokio::process::Command::new(rustup_path.to_str().unwrap())
                 .args(["-y", "--no-modify-path"])
-                .status()?;
+                .status()
+                .await?;
 
             if !status.success() {
                 log::info!(
-                    "failed to install rustc toolchain (x86_64-unknown-linux-musl): {}",
+                    "failed to install rustc toolchain ({target}): {}",
                     status.to_string()
                 );
                 return Ok(());
             }
         }
 
-        log::info!("adding target for x86_64-unknown-linux-musl");
+        log::info!("adding target for {target}");
         //rustup default stable
-        std::process::Command::new(installed_rustup_path.to_str().unwrap())
-            .args(["target", "add", "x86_64-unknown-linux-musl"])
-            .status()?;
+        tokio::process::Command::new(installed_rustup_path.to_str().unwrap())
+            .args(["target", "add", target])
+            .status()
+            .await?;
 
         Ok(())
     }
@@ -380,16 +605,14 @@ impl AgentService {
         Ok(false)
     }
 
-    async fn sync_agent_bin_path(&self, output_path: &Path) -> eyre::Result<PathBuf> {
-        let agent_path = output_path
-            .join("x86_64-unknown-linux-musl")
-            .join("release")
-            .join("agent");
+    async fn sync_agent_bin_path(&self, output_path: &Path, target: &str) -> eyre::Result<PathBuf> {
+        let agent_path = output_path.join(target).join("release").join("agent");
         if !agent_path.exists() {
             return Err(eyre!("could not find the agent: {agent_path:?}"));
         }
         let mut l = self.inner.lock().await;
-        l.agent_bin_path = agent_path.to_str().unwrap().to_owned();
+        l.agent_bin_paths
+            .insert(target.to_owned(), agent_path.to_str().unwrap().to_owned());
         drop(l);
         Ok(agent_path)
     }
@@ -420,3 +643,13 @@ impl AgentService {
         hex::encode(joined_hash)
     }
 }
+
+/// the target triple of the agent for the `uname -m` of a server
+pub fn target_triple(arch: &str) -> eyre::Result<&'static str> {
+    match arch {
+        "x86_64" | "amd64" => Ok("x86_64-unknown-linux-musl"),
+        "aarch64" | "arm64" => Ok("aarch64-unknown-linux-musl"),
+        "armv7l" | "armv7" | "armv8l" => Ok("armv7-unknown-linux-musleabihf"),
+        arch => Err(eyre!("there is no agent build for {arch}")),
+    }
+}
@@ -0,0 +1,73 @@
+use crate::libs::agent_service;
+use crate::libs::shared_state::SharedState;
+use crate::prelude::Res;
+use agent_shared::{AgentRequest, AgentResponse};
+use chrono::Utc;
+use eyre::eyre;
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// every chunk is a request of its own
+const CHUNK_SIZE: usize = 512 * 1024;
+const CHUNK_TIMEOUT: Duration = Duration::from_secs(30);
+/// the agent restores its previous binary after 120 seconds without a handshake
+const RECONNECT_TIMEOUT: Duration = Duration::from_secs(150);
+
+/// push the last agent build to the connected agent of the server over the agent channel,
+/// then wait for the updated agent to reconnect
+pub async fn update(state: &SharedState, server_id: &str) -> Res {
+    let server = state
+        .db_driver
+        .get_server_by_id(server_id.to_owned())?
+        .ok_or(eyre!("server not found"))?;
+    // the agents deployed before the arch was recorded are x86_64 builds
+    let target = agent_service::target_triple(server.arch.as_deref().unwrap_or("x86_64"))?;
+    let binary = state.agent_service.agent_binary(target).await?;
+    let sha256 = hex::encode(Sha256::digest(&binary));
+    log::info!(
+        "updating the agent of {server_id} ({} bytes, {sha256})",
+        binary.len()
+    );
+
+    let size = binary.len() as u64;
+    send(state, server_id, AgentRequest::BeginUpdate { size, sha256 }).await?;
+    for (i, chunk) in binary.chunks(CHUNK_SIZE).enumerate() {
+        let request = AgentRequest::UpdateChunk {
+            offset: (i * CHUNK_SIZE) as u64,
+            data: chunk.to_vec(),
+        };
+        send(state, server_id, request).await?;
+    }
+    let applied_at = Utc::now().naive_utc();
+    send(state, server_id, AgentRequest::ApplyUpdate).await?;
+
+    log::info!("waiting for the updated agent of {server_id} to reconnect");
+    let instant = Instant::now();
+    while instant.elapsed() < RECONNECT_TIMEOUT {
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        let reconnected = state
+            .agent_hub
+            .session(server_id)
+            .is_some_and(|s| s.connected_since > applied_at && s.agent_version.is_some());
+        if reconnected {
+            log::info!("the agent of {server_id} is updated");
+            return Ok(());
+        }
+    }
+    Err(eyre!(
+        "the updated agent of {server_id} did not reconnect, it restores its previous version"
+    ))
+}
+
+async fn send(state: &SharedState, server_id: &str, request: AgentRequest) -> Res {
+    match state
+        .agent_hub
+        .request(server_id, request, CHUNK_TIMEOUT)
+        .await?
+    {
+        AgentResponse::Done => Ok(()),
+        AgentResponse::Error { message } => Err(eyre!("the agent rejected the update: {message}")),
+        response => Err(eyre!("unexpected response of the agent: {response:?}")),
+    }
+}
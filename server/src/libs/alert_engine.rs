@@ -0,0 +1,218 @@
+use crate::libs::notifier::Notification;
+use crate::libs::shared_state::SharedState;
+use crate::models::alert::{Alert, AlertCondition, AlertRule, AlertStatus};
+use crate::models::server_metric::ServerMetric;
+use crate::prelude::Res;
+use chrono::{NaiveDateTime, Utc};
+use std::time::Duration;
+
+/// how often the rules that don't depend on incoming metrics (e.g. agent offline) are evaluated
+const EVALUATION_INTERVAL: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlertEvent {
+    Fired,
+    Resolved,
+}
+
+/// evaluate the metric based rules of the server against a new sample
+pub fn on_metric(state: &SharedState, metric: &ServerMetric) -> Res {
+    let now = Utc::now().naive_utc();
+    for rule in applicable_rules(state, &metric.server_id)? {
+        let Some(value) = metric_value(&rule.condition, metric) else {
+            continue;
+        };
+        let breached = match rule.condition {
+            AlertCondition::CpuAbove(limit) | AlertCondition::MemoryAbove(limit) => value > limit,
+            AlertCondition::DiskFreeBelow(limit) => value < limit,
+            AlertCondition::AgentOffline => continue,
+        };
+        evaluate(
+            state,
+            &rule,
+            &metric.server_id,
+            breached,
+            Some(value),
+            now,
+            now,
+        )?;
+    }
+    Ok(())
+}
+
+/// periodically evaluates the agent offline rules
+pub fn run(state: SharedState) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(EVALUATION_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(e) = evaluate_offline_rules(&state) {
+                log::error!("failed to evaluate alert rules: {e}");
+            }
+        }
+    });
+}
+
+fn evaluate_offline_rules(state: &SharedState) -> Res {
+    let now = Utc::now().naive_utc();
+    let rules = state
+        .db_driver
+        .all_alert_rules()?
+        .into_iter()
+        .filter(|r| r.enabled && matches!(r.condition, AlertCondition::AgentOffline))
+        .collect::<Vec<_>>();
+    if rules.is_empty() {
+        return Ok(());
+    }
+
+    for server in state.db_driver.all_servers()? {
+        // servers without an agent can't go offline
+        let Some(last_seen) = server.last_seen else {
+            continue;
+        };
+        let online = state.agent_hub.session(&server.id).is_some();
+        let offline_secs = (now - last_seen).num_seconds().max(0) as f64;
+        for rule in rules
+            .iter()
+            .filter(|r| r.server_id.as_ref().is_none_or(|id| id.eq(&server.id)))
+        {
+            let value = (!online).then_some(offline_secs);
+            // the agent has been offline since it was last seen, not since the first evaluation
+            evaluate(state, rule, &server.id, !online, value, last_seen, now)?;
+        }
+    }
+    Ok(())
+}
+
+fn applicable_rules(state: &SharedState, server_id: &str) -> eyre::Result<Vec<AlertRule>> {
+    Ok(state
+        .db_driver
+        .all_alert_rules()?
+        .into_iter()
+        .filter(|r| r.enabled && r.server_id.as_ref().is_none_or(|id| id.eq(server_id)))
+        .collect())
+}
+
+fn metric_value(condition: &AlertCondition, metric: &ServerMetric) -> Option<f64> {
+    match condition {
+        AlertCondition::CpuAbove(_) => Some(metric.system_status.as_ref()?.cpu as f64),
+        AlertCondition::MemoryAbove(_) => {
+            let used = metric.system_status.as_ref()?.memory as f64;
+            let total = metric.system_info.memory as f64;
+            (total > 0.0).then(|| used / total * 100.0)
+        }
+        AlertCondition::DiskFreeBelow(_) => metric
+            .system_info
+            .disks
+            .iter()
+            .filter(|d| d.size > 0)
+            .map(|d| d.available as f64 / d.size as f64 * 100.0)
+            .min_by(|a, b| a.total_cmp(b)),
+        AlertCondition::AgentOffline => None,
+    }
+}
+
+/// move the alert of the rule on the server to its next state,
+/// `breached_since` is when a new breach started and counts towards `for_secs`
+fn evaluate(
+    state: &SharedState,
+    rule: &AlertRule,
+    server_id: &str,
+    breached: bool,
+    value: Option<f64>,
+    breached_since: NaiveDateTime,
+    now: NaiveDateTime,
+) -> Res {
+    let current = state
+        .db_driver
+        .get_alert(&rule.id, server_id)?
+        .filter(|a| a.status != AlertStatus::Resolved);
+
+    let event = match (current, breached) {
+        (None, false) => return Ok(()),
+        (None, true) => {
+            let mut alert = Alert {
+                rule_id: rule.id.clone(),
+                server_id: server_id.to_owned(),
+                status: AlertStatus::Pending,
+                pending_since: breached_since,
+                fired_at: None,
+                resolved_at: None,
+                value,
+            };
+            let event = fire_if_due(&mut alert, rule, now);
+            state.db_driver.upsert_alert(alert)?;
+            event
+        }
+        (Some(mut alert), true) => {
+            alert.value = value;
+            let event = fire_if_due(&mut alert, rule, now);
+            state.db_driver.upsert_alert(alert)?;
+            event
+        }
+        // the condition recovered before the alert fired
+        (Some(alert), false) if alert.status == AlertStatus::Pending => {
+            state.db_driver.remove_alert(alert)?;
+            None
+        }
+        (Some(mut alert), false) => {
+            alert.status = AlertStatus::Resolved;
+            alert.resolved_at = Some(now);
+            alert.value = value;
+            state.db_driver.upsert_alert(alert)?;
+            Some(AlertEvent::Resolved)
+        }
+    };
+
+    if let Some(event) = event {
+        log::warn!(
+            "alert `{}` on {server_id}: {event:?} ({value:?})",
+            rule.name
+        );
+        notify(state, rule, server_id, event, value, now)?;
+    }
+    Ok(())
+}
+
+fn notify(
+    state: &SharedState,
+    rule: &AlertRule,
+    server_id: &str,
+    event: AlertEvent,
+    value: Option<f64>,
+    now: NaiveDateTime,
+) -> Res {
+    let server_name = state
+        .db_driver
+        .get_server_by_id(server_id.to_owned())?
+        .map(|s| s.name);
+    let name = server_name.as_deref().unwrap_or(server_id);
+    let (status, message) = match event {
+        AlertEvent::Fired => ("firing", format!("`{}` is firing on {name}", rule.name)),
+        AlertEvent::Resolved => ("resolved", format!("`{}` is resolved on {name}", rule.name)),
+    };
+
+    state.notifier.notify(
+        &rule.channels,
+        Notification {
+            title: format!("[{status}] {}", rule.name),
+            message,
+            status: status.into(),
+            server_id: Some(server_id.to_owned()),
+            server_name,
+            value,
+            time: now,
+        },
+    );
+    Ok(())
+}
+
+fn fire_if_due(alert: &mut Alert, rule: &AlertRule, now: NaiveDateTime) -> Option<AlertEvent> {
+    let due = (now - alert.pending_since).num_seconds() >= rule.for_secs as i64;
+    if alert.status == AlertStatus::Pending && due {
+        alert.status = AlertStatus::Firing;
+        alert.fired_at = Some(now);
+        return Some(AlertEvent::Fired);
+    }
+    None
+}
@@ -43,7 +43,13 @@ impl ApiResponse {
             status: StatusCode::UNAUTHORIZED,
         }
     }
-    #[allow(unused)]
+    pub fn forbidden(message: &str) -> Self {
+        Self {
+            data: None,
+            message: message.into(),
+            status: StatusCode::FORBIDDEN,
+        }
+    }
     pub fn conflict(message: &str) -> Self {
         Self {
             data: None,
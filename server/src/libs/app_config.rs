@@ -11,6 +11,7 @@ use std::sync::{Arc, LazyLock};
 static CONFIG_PATH: LazyLock<PathBuf> =
     LazyLock::new(|| dirs::config_dir().unwrap().join("managers_server.json"));
 const DB_NAME: &str = "native.db";
+const MASTER_KEY_NAME: &str = "master.key";
 
 #[derive(Parser, Debug, Serialize, Deserialize, Default, Clone)]
 #[command(version, about, long_about = None)]
@@ -33,6 +34,31 @@ pub struct AppConfig {
 
     #[arg(short,long,default_value_t=default_db_path(), help="storage path for the api")]
     pub db_path: String,
+
+    #[arg(long, default_value_t = default_metrics_retention_hours(), help = "how long the metric history of the servers is kept (in hours)")]
+    #[serde(default = "default_metrics_retention_hours")]
+    pub metrics_retention_hours: u64,
+
+    #[arg(long, default_value_t = default_master_key_path(), help = "path of the key used to encrypt the stored server credentials, generated if it doesn't exist")]
+    #[serde(default = "default_master_key_path")]
+    pub master_key_path: String,
+
+    #[arg(long, action = ArgAction::SetTrue, help = "accept agents that connect without tls, only needed until the agents deployed before the tls support are deployed again")]
+    pub allow_plaintext_agents: bool,
+
+    #[arg(long, action = ArgAction::SetTrue, help = "accept the agent tokens signed with the password, only needed until the agents deployed before the agent credentials are rotated or deployed again")]
+    pub allow_legacy_agent_tokens: bool,
+
+    #[arg(long, default_value = "", help = "load prebuilt agents from this directory (see `agent_bundle`) instead of building them, nothing is downloaded or compiled. overrides the stored config")]
+    pub agent_bundle_dir: String,
+
+    /// signs the access tokens of the api users, generated on the first start
+    #[arg(skip)]
+    pub jwt_secret: String,
+
+    /// signs the tokens of the agents, generated on the first start
+    #[arg(skip)]
+    pub agent_secret: String,
 }
 
 #[derive(Clone)]
@@ -52,13 +78,17 @@ impl AppConfig {
         }
         if !self.pwd.is_empty() {
             log::info!(
-                "the --pwd flag was ignored, to change the password use --init flag with the new password"
+                "the --pwd flag was ignored, to reset the password of `admin` use --init flag with the new password"
             );
         }
         log::info!("loading {}", CONFIG_PATH.deref().to_str().unwrap());
         let str = tokio::fs::read_to_string(CONFIG_PATH.deref()).await?;
-        *self = serde_json::from_str(&str)?;
-        log::info!("password: {}", self.pwd);
+        let cli = std::mem::replace(self, serde_json::from_str(&str)?);
+        self.apply_runtime_flags(cli);
+        // older configs don't have these secrets yet
+        if self.generate_secrets() {
+            tokio::fs::write(CONFIG_PATH.deref(), serde_json::to_vec(&self)?).await?;
+        }
         Ok(())
     }
     
@@ -71,6 +101,15 @@ impl AppConfig {
                 self.pwd
             );
         }
+        // keep the secrets of the previous config, changing the password must not invalidate the tokens
+        if tokio::fs::try_exists(CONFIG_PATH.deref()).await? {
+            let str = tokio::fs::read_to_string(CONFIG_PATH.deref()).await?;
+            if let Ok(previous) = serde_json::from_str::<AppConfig>(&str) {
+                self.jwt_secret = previous.jwt_secret;
+                self.agent_secret = previous.agent_secret;
+            }
+        }
+        self.generate_secrets();
         if !tokio::fs::try_exists(&self.db_path).await? {
             let base = PathBuf::from_str(&self.db_path)?;
             let dir = base.parent().ok_or(eyre!(""))?;
@@ -80,10 +119,45 @@ impl AppConfig {
         tokio::fs::write(CONFIG_PATH.deref(), &slf).await?;
         Ok(())
     }
+
+    /// the flags that apply to a single run are taken from the cli even when the config is loaded,
+    /// they are only stored with `--init`
+    fn apply_runtime_flags(&mut self, cli: AppConfig) {
+        self.allow_plaintext_agents |= cli.allow_plaintext_agents;
+        self.allow_legacy_agent_tokens |= cli.allow_legacy_agent_tokens;
+        if !cli.agent_bundle_dir.is_empty() {
+            self.agent_bundle_dir = cli.agent_bundle_dir;
+        }
+    }
+
+    /// generate the missing signing secrets, returns true if any was generated
+    fn generate_secrets(&mut self) -> bool {
+        let mut generated = false;
+        for secret in [&mut self.jwt_secret, &mut self.agent_secret] {
+            if secret.is_empty() {
+                *secret = generate_secret();
+                generated = true;
+            }
+        }
+        generated
+    }
 }
 fn default_db_path() -> String {
     DATA_DIR_PATH.join(DB_NAME).to_str().unwrap().to_owned()
 }
+fn default_master_key_path() -> String {
+    DATA_DIR_PATH
+        .join(MASTER_KEY_NAME)
+        .to_str()
+        .unwrap()
+        .to_owned()
+}
+fn generate_secret() -> String {
+    cuid2::CuidConstructor::default().with_length(32).create_id()
+}
+fn default_metrics_retention_hours() -> u64 {
+    24 * 7
+}
 impl From<AppConfig> for AppConfigRef {
     fn from(value: AppConfig) -> Self {
         AppConfigRef {
@@ -0,0 +1,98 @@
+use crate::libs::db_driver::DbDriver;
+use crate::models::audit_entry::AuditEntry;
+use crate::models::user::User;
+use chrono::Utc;
+use serde_json::Value;
+
+/// values of these keys are never written to the audit log, at any depth
+const REDACTED_KEYS: &[&str] = &[
+    "secret",
+    "password",
+    "passphrase",
+    "key",
+    "token",
+    "refresh_token",
+    "stdin",
+    "headers",
+];
+const REDACTED: &str = "[redacted]";
+
+pub struct AuditRecord<'a> {
+    pub user: &'a User,
+    pub action: String,
+    pub path: String,
+    pub server_id: Option<String>,
+    pub params: Option<String>,
+    pub status: u16,
+    pub success: bool,
+}
+
+/// append the record to the audit log, failures are only logged so they never fail the action itself.
+///
+/// returns the stored entry, see [`finish`]
+pub fn record(db_driver: &DbDriver, record: AuditRecord) -> Option<AuditEntry> {
+    let entry = AuditEntry {
+        id: cuid2::create_id(),
+        time: Utc::now().naive_utc(),
+        user_id: record.user.id.clone(),
+        username: record.user.username.clone(),
+        action: record.action,
+        path: record.path,
+        server_id: record.server_id,
+        params: record.params,
+        status: record.status,
+        success: record.success,
+    };
+    log::info!(
+        "audit: {} {} ({})",
+        entry.username,
+        entry.action,
+        entry.status
+    );
+    match db_driver.add_audit_entry(entry.clone()) {
+        Ok(_) => Some(entry),
+        Err(e) => {
+            log::error!("failed to write the audit log: {e}");
+            None
+        }
+    }
+}
+
+/// set the result of an action that was recorded before it finished (e.g. a streamed command)
+pub fn finish(db_driver: &DbDriver, mut entry: AuditEntry, success: bool) {
+    entry.success = success;
+    if let Err(e) = db_driver.update_audit_entry(entry) {
+        log::error!("failed to update the audit log: {e}");
+    }
+}
+
+/// the json body with the secrets replaced, `None` for an empty body
+pub fn redacted_params(body: &[u8]) -> Option<String> {
+    if body.is_empty() {
+        return None;
+    }
+    match serde_json::from_slice::<Value>(body) {
+        Ok(mut value) => {
+            redact(&mut value);
+            Some(value.to_string())
+        }
+        // there is no way to tell what is secret in a body that isn't json
+        Err(_) => Some(REDACTED.into()),
+    }
+}
+
+pub fn redact(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                if REDACTED_KEYS.contains(&key.as_str()) {
+                    *value = Value::String(REDACTED.into());
+                } else {
+                    redact(value);
+                }
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
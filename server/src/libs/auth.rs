@@ -0,0 +1,152 @@
+use crate::libs::db_driver::DbDriver;
+use crate::models::user::{Role, User};
+use crate::prelude::Res;
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::SaltString;
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use chrono::{TimeDelta, Utc};
+use eyre::eyre;
+use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+use std::sync::LazyLock;
+
+/// checked when the user doesn't exist, so the response time doesn't reveal which usernames exist
+static DUMMY_HASH: LazyLock<String> = LazyLock::new(|| {
+    hash_password(&cuid2::create_id()).expect("failed to hash the dummy password")
+});
+const ACCESS_TOKEN_TTL: TimeDelta = TimeDelta::minutes(15);
+const REFRESH_TOKEN_TTL: TimeDelta = TimeDelta::days(7);
+
+/// claims of the tokens issued to the users of the api, see [`TokenClaims`](super::TokenClaims) for the agents
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UserClaims {
+    /// user id
+    pub sub: String,
+    pub username: String,
+    pub typ: TokenType,
+    pub iat: usize,
+    pub exp: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenType {
+    Access,
+    Refresh,
+}
+
+#[derive(Serialize)]
+pub struct TokenPair {
+    pub access_token: String,
+    pub refresh_token: String,
+    /// lifetime of the access token in seconds
+    pub expires_in: i64,
+}
+
+pub fn hash_password(password: &str) -> eyre::Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|h| h.to_string())
+        .map_err(|e| eyre!("failed to hash the password: {e}"))
+}
+
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    let Ok(hash) = PasswordHash::new(hash) else {
+        return false;
+    };
+    Argon2::default()
+        .verify_password(password.as_bytes(), &hash)
+        .is_ok()
+}
+
+/// an unknown user costs as much as a wrong password
+pub fn verify_user_password(password: &str, user: Option<&User>) -> bool {
+    match user {
+        Some(user) => verify_password(password, &user.password_hash),
+        None => {
+            verify_password(password, &DUMMY_HASH);
+            false
+        }
+    }
+}
+
+pub fn create_token_pair(secret: &str, user_id: &str, username: &str) -> eyre::Result<TokenPair> {
+    Ok(TokenPair {
+        access_token: create_token(secret, user_id, username, TokenType::Access)?,
+        refresh_token: create_token(secret, user_id, username, TokenType::Refresh)?,
+        expires_in: ACCESS_TOKEN_TTL.num_seconds(),
+    })
+}
+
+fn create_token(
+    secret: &str,
+    user_id: &str,
+    username: &str,
+    typ: TokenType,
+) -> eyre::Result<String> {
+    let now = Utc::now();
+    let ttl = match typ {
+        TokenType::Access => ACCESS_TOKEN_TTL,
+        TokenType::Refresh => REFRESH_TOKEN_TTL,
+    };
+    let claims = UserClaims {
+        sub: user_id.to_owned(),
+        username: username.to_owned(),
+        typ,
+        iat: now.timestamp() as usize,
+        exp: (now + ttl).timestamp() as usize,
+    };
+    Ok(encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(secret.as_bytes()),
+    )?)
+}
+
+/// validate the signature and the expiration of the token and make sure it is of the expected type
+pub fn decode_token(secret: &str, token: &str, typ: TokenType) -> eyre::Result<UserClaims> {
+    let claims = decode::<UserClaims>(
+        token,
+        &DecodingKey::from_secret(secret.as_bytes()),
+        &Validation::default(),
+    )?
+    .claims;
+    if claims.typ != typ {
+        return Err(eyre!("unexpected token type"));
+    }
+    Ok(claims)
+}
+
+/// create the `admin` user with the password of the config if there are no users yet.
+///
+/// with `reset` (`--init --pwd ..`) the password of an existing `admin` is replaced and its role restored,
+/// this is the way back in when every admin is locked out
+pub fn bootstrap_admin(db_driver: &DbDriver, pwd: &str, reset: bool) -> Res {
+    if reset {
+        if let Some(mut admin) = db_driver.get_user_by_username("admin")? {
+            log::info!("resetting the password of the `admin` user");
+            admin.password_hash = hash_password(pwd)?;
+            admin.role = Role::Admin;
+            db_driver.update_user(admin)?;
+            return Ok(());
+        }
+    }
+    if !db_driver.all_users()?.is_empty() && !reset {
+        return Ok(());
+    }
+    if pwd.is_empty() {
+        return Err(eyre!(
+            "there are no users and no password is configured, reinitialize the server with --init"
+        ));
+    }
+    log::info!("no users found, creating the `admin` user with the configured password");
+    db_driver.add_user(User {
+        id: cuid2::create_id(),
+        username: "admin".into(),
+        password_hash: hash_password(pwd)?,
+        created_at: Utc::now().naive_utc(),
+        role: Role::Admin,
+        server_groups: vec![],
+    })
+}
@@ -1,17 +1,39 @@
-use crate::models::server::Server;
+use crate::models::agent_credential::{AgentCredential, RevokedAgentToken};
+use crate::models::alert::{Alert, AlertRule};
+use crate::models::audit_entry::AuditEntry;
+use crate::models::deploy_job::{DeployJob, DeployStatus};
+use crate::models::known_host::KnownHost;
+use crate::models::notification_channel::NotificationChannel;
+use crate::models::server::{Server, ServerV1};
 use crate::models::server_metric::ServerMetric;
+use crate::models::user::{User, UserKey};
 use crate::prelude::Res;
+use chrono::{NaiveDateTime, TimeDelta, Utc};
 use eyre::eyre;
 use itertools::Itertools;
 use native_db::{Database, Models};
+use std::ops::Bound;
 use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::{Arc, LazyLock};
 
+/// the samples removed in one transaction by [`DbDriver::prune_metrics`]
+const PRUNE_METRICS_BATCH: usize = 1000;
+
 static MODELS: LazyLock<Models> = LazyLock::new(|| {
     let mut models = Models::new();
+    models.define::<ServerV1>().unwrap();
     models.define::<Server>().unwrap();
     models.define::<ServerMetric>().unwrap();
+    models.define::<KnownHost>().unwrap();
+    models.define::<AlertRule>().unwrap();
+    models.define::<Alert>().unwrap();
+    models.define::<NotificationChannel>().unwrap();
+    models.define::<User>().unwrap();
+    models.define::<AuditEntry>().unwrap();
+    models.define::<AgentCredential>().unwrap();
+    models.define::<RevokedAgentToken>().unwrap();
+    models.define::<DeployJob>().unwrap();
     models
 });
 
@@ -21,17 +43,23 @@ pub struct DbDriver {
 }
 
 impl DbDriver {
-    pub(crate) fn new(db_path: &str) -> Self {
-        let db_path = PathBuf::from_str(db_path).unwrap();
+    pub(crate) fn new(db_path: &str) -> eyre::Result<Self> {
+        let db_path = PathBuf::from_str(db_path)?;
         log::info!("loading db: {db_path:?}");
         let builder = native_db::Builder::new();
         let db: Database = if db_path.exists() {
-            builder.open(&MODELS, db_path.as_path()).unwrap()
+            builder.open(&MODELS, db_path.as_path())?
         } else {
-            builder.create(&MODELS, db_path.as_path()).unwrap()
+            builder.create(&MODELS, db_path.as_path())?
         };
 
-        Self { db: Arc::new(db) }
+        // seal the plaintext secrets of the old server records
+        let t = db.rw_transaction()?;
+        t.migrate::<Server>()
+            .map_err(|e| eyre!("failed to migrate the servers: {e}"))?;
+        t.commit()?;
+
+        Ok(Self { db: Arc::new(db) })
     }
     pub fn all_servers(&self) -> eyre::Result<Vec<Server>> {
         let t = self.db.r_transaction()?;
@@ -39,8 +67,7 @@ impl DbDriver {
         Ok(t.scan()
             .primary::<Server>()?
             .all()?
-            .map(|f| f.unwrap())
-            .collect_vec())
+            .collect::<Result<Vec<_>, _>>()?)
     }
 
     pub fn add_server(&self, server: Server) -> Res {
@@ -62,29 +89,602 @@ impl DbDriver {
         Ok(update)
     }
 
+    pub fn set_last_seen(&self, server_id: &str, last_seen: NaiveDateTime) -> Res {
+        let t = self.db.rw_transaction()?;
+        let Some(mut server) = t.get().primary::<Server>(server_id)? else {
+            return Ok(());
+        };
+        server.last_seen = Some(last_seen);
+        t.upsert(server)?;
+        t.commit()?;
+        Ok(())
+    }
+
+    pub fn set_server_arch(&self, server_id: &str, arch: &str) -> Res {
+        let t = self.db.rw_transaction()?;
+        let Some(mut server) = t.get().primary::<Server>(server_id)? else {
+            return Ok(());
+        };
+        server.arch = Some(arch.to_owned());
+        t.upsert(server)?;
+        t.commit()?;
+        Ok(())
+    }
+
     pub fn delete_server(&self, id: String) -> Res {
         let r = self.db.rw_transaction()?;
         let item = r
             .get()
-            .primary::<Server>(id)?
+            .primary::<Server>(id.clone())?
             .ok_or(eyre!("server not found"))?;
         r.remove(item)?;
+        if let Some(known_host) = r.get().primary::<KnownHost>(id.clone())? {
+            r.remove(known_host)?;
+        }
+        if let Some(credential) = r.get().primary::<AgentCredential>(id.clone())? {
+            r.remove(credential.clone())?;
+            for token in revoked_tokens(credential, None, "server deleted") {
+                r.upsert(token)?;
+            }
+        }
+        // a running job (e.g. the removal of the agent that deletes the server) is kept until it finishes
+        let jobs: Vec<DeployJob> = r
+            .scan()
+            .primary::<DeployJob>()?
+            .all()?
+            .filter_ok(|j| j.server_id.eq(&id) && j.finished_at.is_some())
+            .try_collect()?;
+        for job in jobs {
+            r.remove(job)?;
+        }
+        // the rules of the server and its alerts, including the ones of the global rules
+        let rules: Vec<AlertRule> = r
+            .scan()
+            .primary::<AlertRule>()?
+            .all()?
+            .filter_ok(|rule| rule.server_id.as_ref().is_some_and(|s| s.eq(&id)))
+            .try_collect()?;
+        for rule in rules {
+            r.remove(rule)?;
+        }
+        let alerts: Vec<Alert> = r
+            .scan()
+            .primary::<Alert>()?
+            .all()?
+            .filter_ok(|a| a.server_id.eq(&id))
+            .try_collect()?;
+        for alert in alerts {
+            r.remove(alert)?;
+        }
         r.commit()?;
         Ok(())
     }
 
+    pub fn get_known_host(&self, server_id: &str) -> eyre::Result<Option<KnownHost>> {
+        let r = self.db.r_transaction()?;
+        Ok(r.get().primary::<KnownHost>(server_id)?)
+    }
+
+    /// pin the host key of the server, returns the previously pinned key
+    pub fn set_known_host(&self, known_host: KnownHost) -> eyre::Result<Option<KnownHost>> {
+        let t = self.db.rw_transaction()?;
+        let old = t.upsert(known_host)?;
+        t.commit()?;
+        Ok(old)
+    }
+
+    /// append a new sample to the metric history of the server
     pub fn add_metric(&self, metric: ServerMetric) -> Res {
         let t = self.db.rw_transaction()?;
-        t.upsert(metric)?;
+        t.insert(metric)?;
+        t.commit()?;
+        Ok(())
+    }
+
+    /// all the samples of the server between `from` and `to` (inclusive), oldest first
+    pub fn get_metrics_range(
+        &self,
+        server_id: &str,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+    ) -> eyre::Result<Vec<ServerMetric>> {
+        let t = self.db.r_transaction()?;
+        let metrics = t
+            .scan()
+            .primary::<ServerMetric>()?
+            .range(ServerMetric::key(server_id, &from)..=ServerMetric::key(server_id, &to))?
+            .try_collect()?;
+        Ok(metrics)
+    }
+
+    pub fn get_latest_metric(&self, server_id: &str) -> eyre::Result<Option<ServerMetric>> {
+        let t = self.db.r_transaction()?;
+        let latest = t
+            .scan()
+            .primary::<ServerMetric>()?
+            .range(
+                ServerMetric::key(server_id, &NaiveDateTime::UNIX_EPOCH)
+                    ..=ServerMetric::key(server_id, &NaiveDateTime::MAX),
+            )?
+            .next_back()
+            .transpose()?;
+        Ok(latest)
+    }
+
+    /// remove every sample older than `before`, returns the number of removed samples.
+    ///
+    /// the samples are removed server by server in batches, so the writers are never blocked for long
+    pub fn prune_metrics(&self, before: NaiveDateTime) -> eyre::Result<usize> {
+        let mut removed = 0;
+        let mut after: Bound<String> = Bound::Unbounded;
+        loop {
+            // the keys are sorted by server, the first one past the last server belongs to the next one
+            let first = {
+                let r = self.db.r_transaction()?;
+                r.scan()
+                    .primary::<ServerMetric>()?
+                    .range((after.clone(), Bound::Unbounded))?
+                    .next()
+                    .transpose()?
+            };
+            let Some(first) = first else {
+                break;
+            };
+            let server_id = first.server_id;
+            loop {
+                let t = self.db.rw_transaction()?;
+                // the keys of another server can fall into the range if its id starts with `{server_id}:`
+                let expired: Vec<ServerMetric> = t
+                    .scan()
+                    .primary::<ServerMetric>()?
+                    .range(
+                        ServerMetric::key(&server_id, &NaiveDateTime::UNIX_EPOCH)
+                            ..ServerMetric::key(&server_id, &before),
+                    )?
+                    .filter_ok(|m| m.server_id.eq(&server_id))
+                    .take(PRUNE_METRICS_BATCH)
+                    .try_collect()?;
+                let count = expired.len();
+                for metric in expired {
+                    t.remove(metric)?;
+                }
+                t.commit()?;
+                removed += count;
+                if count < PRUNE_METRICS_BATCH {
+                    break;
+                }
+            }
+            // every key of the server is at or below its key of the latest possible time
+            after = Bound::Excluded(ServerMetric::key(&server_id, &NaiveDateTime::MAX));
+        }
+        Ok(removed)
+    }
+
+    pub fn all_alert_rules(&self) -> eyre::Result<Vec<AlertRule>> {
+        let t = self.db.r_transaction()?;
+        Ok(t.scan().primary::<AlertRule>()?.all()?.try_collect()?)
+    }
+
+    pub fn get_alert_rule(&self, id: &str) -> eyre::Result<Option<AlertRule>> {
+        let t = self.db.r_transaction()?;
+        Ok(t.get().primary::<AlertRule>(id)?)
+    }
+
+    pub fn add_alert_rule(&self, rule: AlertRule) -> Res {
+        let t = self.db.rw_transaction()?;
+        t.insert(rule)?;
+        t.commit()?;
+        Ok(())
+    }
+
+    pub fn update_alert_rule(&self, rule: AlertRule) -> eyre::Result<Option<AlertRule>> {
+        let t = self.db.rw_transaction()?;
+        let old = t.upsert(rule)?;
+        t.commit()?;
+        Ok(old)
+    }
+
+    /// remove the rule and every alert it produced
+    pub fn delete_alert_rule(&self, id: String) -> Res {
+        let t = self.db.rw_transaction()?;
+        let rule = t
+            .get()
+            .primary::<AlertRule>(id.clone())?
+            .ok_or(eyre!("rule not found"))?;
+        t.remove(rule)?;
+        let alerts: Vec<Alert> = t
+            .scan()
+            .primary::<Alert>()?
+            .start_with(format!("{id}:"))?
+            .try_collect()?;
+        for alert in alerts {
+            t.remove(alert)?;
+        }
+        t.commit()?;
+        Ok(())
+    }
+
+    pub fn all_alerts(&self) -> eyre::Result<Vec<Alert>> {
+        let t = self.db.r_transaction()?;
+        Ok(t.scan().primary::<Alert>()?.all()?.try_collect()?)
+    }
+
+    pub fn get_alert(&self, rule_id: &str, server_id: &str) -> eyre::Result<Option<Alert>> {
+        let t = self.db.r_transaction()?;
+        Ok(t.get().primary::<Alert>(Alert::key(rule_id, server_id))?)
+    }
+
+    pub fn upsert_alert(&self, alert: Alert) -> Res {
+        let t = self.db.rw_transaction()?;
+        t.upsert(alert)?;
+        t.commit()?;
+        Ok(())
+    }
+
+    pub fn remove_alert(&self, alert: Alert) -> Res {
+        let t = self.db.rw_transaction()?;
+        t.remove(alert)?;
+        t.commit()?;
+        Ok(())
+    }
+
+    pub fn all_notification_channels(&self) -> eyre::Result<Vec<NotificationChannel>> {
+        let t = self.db.r_transaction()?;
+        Ok(t.scan()
+            .primary::<NotificationChannel>()?
+            .all()?
+            .try_collect()?)
+    }
+
+    pub fn get_notification_channel(
+        &self,
+        id: String,
+    ) -> eyre::Result<Option<NotificationChannel>> {
+        let t = self.db.r_transaction()?;
+        Ok(t.get().primary::<NotificationChannel>(id)?)
+    }
+
+    pub fn upsert_notification_channel(
+        &self,
+        channel: NotificationChannel,
+    ) -> eyre::Result<Option<NotificationChannel>> {
+        let t = self.db.rw_transaction()?;
+        let old = t.upsert(channel)?;
+        t.commit()?;
+        Ok(old)
+    }
+
+    pub fn delete_notification_channel(&self, id: String) -> Res {
+        let t = self.db.rw_transaction()?;
+        let channel = t
+            .get()
+            .primary::<NotificationChannel>(id)?
+            .ok_or(eyre!("channel not found"))?;
+        t.remove(channel)?;
         t.commit()?;
         Ok(())
     }
-    #[allow(dead_code)]
-    pub fn get_server_metrics(&self, server_id: String) -> eyre::Result<Option<ServerMetric>> {
+
+    pub fn all_users(&self) -> eyre::Result<Vec<User>> {
+        let t = self.db.r_transaction()?;
+        Ok(t.scan().primary::<User>()?.all()?.try_collect()?)
+    }
+
+    pub fn get_user_by_id(&self, id: &str) -> eyre::Result<Option<User>> {
+        let t = self.db.r_transaction()?;
+        Ok(t.get().primary::<User>(id)?)
+    }
+
+    pub fn get_user_by_username(&self, username: &str) -> eyre::Result<Option<User>> {
         let t = self.db.r_transaction()?;
+        Ok(t.get().secondary::<User>(UserKey::username, username)?)
+    }
+
+    pub fn add_user(&self, user: User) -> Res {
+        let t = self.db.rw_transaction()?;
+        t.insert(user)?;
+        t.commit()?;
+        Ok(())
+    }
+
+    pub fn update_user(&self, user: User) -> eyre::Result<Option<User>> {
+        let t = self.db.rw_transaction()?;
+        let old = t.upsert(user)?;
+        t.commit()?;
+        Ok(old)
+    }
+
+    pub fn delete_user(&self, id: &str) -> Res {
+        let t = self.db.rw_transaction()?;
+        let user = t
+            .get()
+            .primary::<User>(id)?
+            .ok_or(eyre!("user not found"))?;
+        t.remove(user)?;
+        t.commit()?;
+        Ok(())
+    }
+
+    pub fn add_audit_entry(&self, entry: AuditEntry) -> Res {
+        let t = self.db.rw_transaction()?;
+        t.insert(entry)?;
+        t.commit()?;
+        Ok(())
+    }
+
+    pub fn update_audit_entry(&self, entry: AuditEntry) -> Res {
+        let t = self.db.rw_transaction()?;
+        t.upsert(entry)?;
+        t.commit()?;
+        Ok(())
+    }
+
+    /// the newest entries between `from` and `to` (inclusive) that match the filter
+    pub fn get_audit_entries(
+        &self,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+        limit: usize,
+        filter: impl Fn(&AuditEntry) -> bool,
+    ) -> eyre::Result<Vec<AuditEntry>> {
+        let t = self.db.r_transaction()?;
+        let to = to + TimeDelta::milliseconds(1);
+        let entries = t
+            .scan()
+            .primary::<AuditEntry>()?
+            .range(AuditEntry::time_key(&from)..AuditEntry::time_key(&to))?
+            .rev()
+            .filter_ok(|e| filter(e))
+            .take(limit)
+            .try_collect()?;
+        Ok(entries)
+    }
+
+    pub fn get_agent_credential(&self, server_id: &str) -> eyre::Result<Option<AgentCredential>> {
+        let r = self.db.r_transaction()?;
+        Ok(r.get().primary::<AgentCredential>(server_id)?)
+    }
+
+    /// store the pending token of a rotation, the current one stays valid until it is confirmed
+    pub fn set_pending_agent_token(&self, server_id: &str, jti: Option<String>) -> Res {
+        let t = self.db.rw_transaction()?;
+        let mut credential = t
+            .get()
+            .primary::<AgentCredential>(server_id)?
+            .ok_or(eyre!("the agent of {server_id} has no credential"))?;
+        credential.pending_jti = jti;
+        t.upsert(credential)?;
+        t.commit()?;
+        Ok(())
+    }
+
+    /// make `credential` the only valid one of its server, the previous tokens are revoked
+    pub fn replace_agent_credential(&self, credential: AgentCredential, reason: &str) -> Res {
+        let t = self.db.rw_transaction()?;
+        if let Some(old) = t.upsert(credential.clone())? {
+            for token in revoked_tokens(old, Some(&credential.jti), reason) {
+                t.upsert(token)?;
+            }
+        }
+        t.commit()?;
+        Ok(())
+    }
+
+    /// revoke every token of the agent, it can't connect until it is deployed again
+    pub fn revoke_agent_credential(&self, server_id: &str) -> eyre::Result<bool> {
+        let t = self.db.rw_transaction()?;
+        let Some(credential) = t.get().primary::<AgentCredential>(server_id)? else {
+            return Ok(false);
+        };
+        t.remove(credential.clone())?;
+        for token in revoked_tokens(credential, None, "revoked") {
+            t.upsert(token)?;
+        }
+        t.commit()?;
+        Ok(true)
+    }
+
+    /// the credential of the agent that presented the client certificate
+    pub fn find_agent_credential_by_cert(
+        &self,
+        fingerprint: &str,
+    ) -> eyre::Result<Option<AgentCredential>> {
+        let r = self.db.r_transaction()?;
+        let credential = r
+            .scan()
+            .primary::<AgentCredential>()?
+            .all()?
+            .filter_ok(|c| c.cert_fingerprint.as_deref() == Some(fingerprint))
+            .next()
+            .transpose()?;
+        Ok(credential)
+    }
+
+    pub fn add_revoked_agent_token(&self, token: RevokedAgentToken) -> Res {
+        let t = self.db.rw_transaction()?;
+        t.upsert(token)?;
+        t.commit()?;
+        Ok(())
+    }
+
+    pub fn is_agent_token_revoked(&self, jti: &str) -> eyre::Result<bool> {
+        let r = self.db.r_transaction()?;
+        Ok(r.get().primary::<RevokedAgentToken>(jti)?.is_some())
+    }
+
+    pub fn get_revoked_agent_tokens(
+        &self,
+        server_id: &str,
+    ) -> eyre::Result<Vec<RevokedAgentToken>> {
+        let r = self.db.r_transaction()?;
+        Ok(r.scan()
+            .primary::<RevokedAgentToken>()?
+            .all()?
+            .filter_ok(|t| t.server_id.eq(server_id))
+            .try_collect()?)
+    }
+
+    pub fn upsert_deploy_job(&self, job: DeployJob) -> Res {
+        let t = self.db.rw_transaction()?;
+        t.upsert(job)?;
+        t.commit()?;
+        Ok(())
+    }
+
+    pub fn get_deploy_job(&self, id: &str) -> eyre::Result<Option<DeployJob>> {
+        let r = self.db.r_transaction()?;
+        Ok(r.get().primary::<DeployJob>(id)?)
+    }
+
+    /// the jobs that were still running when the server stopped can't finish anymore,
+    /// they are marked as failed. the finished jobs of deleted servers are removed
+    pub fn recover_deploy_jobs(&self, now: NaiveDateTime) -> Res {
+        let t = self.db.rw_transaction()?;
+        let jobs: Vec<DeployJob> = t.scan().primary::<DeployJob>()?.all()?.try_collect()?;
+        for mut job in jobs {
+            if job.finished_at.is_none() {
+                job.status = DeployStatus::Failed;
+                job.finished_at = Some(now);
+                job.error = Some("the server restarted while the job was running".into());
+                t.upsert(job)?;
+            } else if t.get().primary::<Server>(job.server_id.clone())?.is_none() {
+                t.remove(job)?;
+            }
+        }
+        t.commit()?;
+        Ok(())
+    }
+
+    /// keep the newest `keep` jobs of the server
+    pub fn prune_deploy_jobs(&self, server_id: &str, keep: usize) -> Res {
+        let jobs = self.get_deploy_jobs(server_id)?;
+        if jobs.len() <= keep {
+            return Ok(());
+        }
+        let t = self.db.rw_transaction()?;
+        for job in jobs.into_iter().skip(keep) {
+            t.remove(job)?;
+        }
+        t.commit()?;
+        Ok(())
+    }
+
+    /// the jobs of the server, newest first
+    pub fn get_deploy_jobs(&self, server_id: &str) -> eyre::Result<Vec<DeployJob>> {
+        let r = self.db.r_transaction()?;
+        let mut jobs: Vec<DeployJob> = r
+            .scan()
+            .primary::<DeployJob>()?
+            .all()?
+            .filter_ok(|j| j.server_id.eq(server_id))
+            .try_collect()?;
+        jobs.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        Ok(jobs)
+    }
+}
+
+/// the current and pending tokens of the credential, except `keep`
+fn revoked_tokens(
+    credential: AgentCredential,
+    keep: Option<&String>,
+    reason: &str,
+) -> Vec<RevokedAgentToken> {
+    let now = Utc::now().naive_utc();
+    [Some(credential.jti), credential.pending_jti]
+        .into_iter()
+        .flatten()
+        .filter(|jti| keep != Some(jti))
+        .map(|jti| RevokedAgentToken {
+            jti,
+            server_id: credential.server_id.clone(),
+            revoked_at: now,
+            reason: reason.to_owned(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use machine_info::{Machine, SystemInfo};
+
+    fn db_driver() -> DbDriver {
+        let path = std::env::temp_dir().join(format!("db-driver-{}.db", cuid2::create_id()));
+        DbDriver::new(path.to_str().unwrap()).unwrap()
+    }
+
+    /// reading the system info is slow, the samples share a copy of it
+    fn system_info() -> SystemInfo {
+        static INFO: LazyLock<serde_json::Value> =
+            LazyLock::new(|| serde_json::to_value(Machine::new().system_info()).unwrap());
+        serde_json::from_value(INFO.clone()).unwrap()
+    }
+
+    fn add_metric(db: &DbDriver, server_id: &str, time: NaiveDateTime) {
+        db.add_metric(ServerMetric {
+            server_id: server_id.into(),
+            time,
+            system_info: system_info(),
+            system_status: None,
+        })
+        .unwrap();
+    }
+
+    fn times(db: &DbDriver, server_id: &str) -> Vec<NaiveDateTime> {
+        db.get_metrics_range(server_id, NaiveDateTime::UNIX_EPOCH, NaiveDateTime::MAX)
+            .unwrap()
+            .into_iter()
+            .map(|m| m.time)
+            .collect()
+    }
+
+    #[test]
+    fn prunes_the_expired_samples_of_every_server() {
+        let db = db_driver();
+        let now = Utc::now().naive_utc();
+        // `a` is a prefix of `a:b`, their keys must not be mixed up
+        let servers = ["a", "a:b", "b"];
+        for server_id in servers {
+            for hours in [3, 2, 0] {
+                add_metric(&db, server_id, now - TimeDelta::hours(hours));
+            }
+        }
+
+        assert_eq!(db.prune_metrics(now - TimeDelta::hours(1)).unwrap(), 6);
+        for server_id in servers {
+            assert_eq!(times(&db, server_id), [now], "{server_id}");
+        }
+        assert_eq!(db.prune_metrics(now - TimeDelta::hours(1)).unwrap(), 0);
+    }
+
+    #[test]
+    fn prunes_more_samples_than_a_batch() {
+        let db = db_driver();
+        let now = Utc::now().naive_utc();
+        let expired = PRUNE_METRICS_BATCH + 5;
+        for secs in 0..expired {
+            add_metric(
+                &db,
+                "a",
+                now - TimeDelta::hours(2) + TimeDelta::seconds(secs as i64),
+            );
+        }
+        add_metric(&db, "a", now);
+        add_metric(&db, "b", now - TimeDelta::hours(2));
+
+        assert_eq!(
+            db.prune_metrics(now - TimeDelta::hours(1)).unwrap(),
+            expired + 1
+        );
+        assert_eq!(times(&db, "a"), [now]);
+        assert!(times(&db, "b").is_empty());
+    }
 
-        t.get()
-            .primary::<ServerMetric>(server_id)
-            .map_err(|e| eyre!("{}", e.to_string()))
+    #[test]
+    fn prunes_an_empty_table() {
+        assert_eq!(
+            db_driver().prune_metrics(Utc::now().naive_utc()).unwrap(),
+            0
+        );
     }
 }
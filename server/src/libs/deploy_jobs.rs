@@ -0,0 +1,187 @@
+use crate::libs::db_driver::DbDriver;
+use crate::libs::shared_state::SharedState;
+use crate::models::deploy_job::{DeployJob, DeployLog, DeployStatus, JobKind};
+use crate::models::server::Server;
+use crate::prelude::Res;
+use chrono::Utc;
+use std::collections::HashSet;
+use std::sync::{Arc, LazyLock, Mutex};
+
+/// the older jobs of a server are removed when a new one starts
+const KEEP_JOBS_PER_SERVER: usize = 20;
+
+/// the servers with a running job, every job issues or revokes the token of the agent
+static RUNNING: LazyLock<Mutex<HashSet<String>>> = LazyLock::new(Default::default);
+
+/// held while a job runs on the server, see [`begin`]
+pub struct JobGuard {
+    server_id: String,
+}
+
+impl Drop for JobGuard {
+    fn drop(&mut self) {
+        RUNNING.lock().unwrap().remove(&self.server_id);
+    }
+}
+
+/// a running deployment, every change is persisted right away so it can be followed with `GET /agents/jobs/{id}`
+#[derive(Clone)]
+pub struct JobLog {
+    db_driver: DbDriver,
+    job: Arc<Mutex<DeployJob>>,
+}
+
+impl JobLog {
+    pub fn log<M: Into<String>>(&self, message: M) {
+        let message = message.into();
+        let mut job = self.job.lock().unwrap();
+        log::info!("[deploy {}] {message}", job.id);
+        job.logs.push(DeployLog {
+            time: Utc::now().naive_utc(),
+            message,
+        });
+        self.persist(&job);
+    }
+
+    pub fn set_status(&self, status: DeployStatus) {
+        let mut job = self.job.lock().unwrap();
+        job.status = status;
+        if matches!(status, DeployStatus::Succeeded | DeployStatus::Failed) {
+            job.finished_at = Some(Utc::now().naive_utc());
+        }
+        self.persist(&job);
+    }
+
+    pub fn fail(&self, error: String) {
+        self.log(format!("failed: {error}"));
+        self.job.lock().unwrap().error = Some(error);
+        self.set_status(DeployStatus::Failed);
+    }
+
+    pub fn force(&self) -> bool {
+        self.job.lock().unwrap().force
+    }
+
+    fn persist(&self, job: &DeployJob) {
+        if let Err(e) = self.db_driver.upsert_deploy_job(job.clone()) {
+            log::error!("failed to persist the deploy job {}: {e}", job.id);
+        }
+    }
+}
+
+/// fail the jobs that were interrupted by a restart, must run before any job is started
+pub fn recover(db_driver: &DbDriver) -> Res {
+    db_driver.recover_deploy_jobs(Utc::now().naive_utc())
+}
+
+/// returns none if a job is already running on the server
+pub fn begin(server_id: &str) -> Option<JobGuard> {
+    if !RUNNING.lock().unwrap().insert(server_id.to_owned()) {
+        return None;
+    }
+    Some(JobGuard {
+        server_id: server_id.to_owned(),
+    })
+}
+
+/// queue a deployment of the agent to the server and run it in the background
+pub fn start(
+    state: &SharedState,
+    server: Server,
+    force: bool,
+    guard: JobGuard,
+) -> eyre::Result<DeployJob> {
+    let (job, job_log) = create(state, &server, JobKind::Deploy, force)?;
+    let state = state.clone();
+    tokio::spawn(async move {
+        match deploy(&state, &server, &job_log).await {
+            Ok(_) => job_log.set_status(DeployStatus::Succeeded),
+            Err(e) => job_log.fail(e.to_string()),
+        }
+        drop(guard);
+    });
+    Ok(job)
+}
+
+/// revoke the token of the agent and remove the agent from the server in the background.
+///
+/// with `delete_server` the server is deleted once the agent is removed, even if that failed
+pub fn start_uninstall(
+    state: &SharedState,
+    server: Server,
+    delete_server: bool,
+    guard: JobGuard,
+) -> eyre::Result<DeployJob> {
+    // the agent must not come back, even if the server can't be reached over ssh
+    state.db_driver.revoke_agent_credential(&server.id)?;
+    state.agent_hub.disconnect(&server.id);
+
+    let (job, job_log) = create(state, &server, JobKind::Uninstall, false)?;
+    job_log.log("revoked the token of the agent");
+    let state = state.clone();
+    tokio::spawn(async move {
+        let result = uninstall(&state, &server, &job_log).await;
+        if delete_server {
+            match state.db_driver.delete_server(server.id.clone()) {
+                Ok(_) => job_log.log("the server is deleted"),
+                Err(e) => job_log.log(format!("failed to delete the server: {e}")),
+            }
+        }
+        match result {
+            Ok(_) => job_log.set_status(DeployStatus::Succeeded),
+            Err(e) => job_log.fail(e.to_string()),
+        }
+        drop(guard);
+    });
+    Ok(job)
+}
+
+fn create(
+    state: &SharedState,
+    server: &Server,
+    kind: JobKind,
+    force: bool,
+) -> eyre::Result<(DeployJob, JobLog)> {
+    let job = DeployJob {
+        id: cuid2::create_id(),
+        server_id: server.id.clone(),
+        status: DeployStatus::Queued,
+        force,
+        created_at: Utc::now().naive_utc(),
+        finished_at: None,
+        logs: vec![],
+        error: None,
+        kind,
+    };
+    state.db_driver.upsert_deploy_job(job.clone())?;
+    state
+        .db_driver
+        .prune_deploy_jobs(&server.id, KEEP_JOBS_PER_SERVER)?;
+
+    let job_log = JobLog {
+        db_driver: state.db_driver.clone(),
+        job: Arc::new(Mutex::new(job.clone())),
+    };
+    Ok((job, job_log))
+}
+
+async fn uninstall(state: &SharedState, server: &Server, job: &JobLog) -> Res {
+    job.set_status(DeployStatus::Uninstalling);
+    state.agent_service.uninstall_agent(server, job).await?;
+    job.log("the agent is uninstalled");
+    Ok(())
+}
+
+async fn deploy(state: &SharedState, server: &Server, job: &JobLog) -> Res {
+    job.set_status(DeployStatus::Uploading);
+    let uploaded = state.agent_service.upload_agent(server, job).await?;
+    if !uploaded && !job.force() && state.agent_hub.session(&server.id).is_some() {
+        job.log("the agent is up to date and connected, use `force` to deploy it again");
+        return Ok(());
+    }
+
+    job.set_status(DeployStatus::Initializing);
+    state.agent_service.init_agent(server, job).await?;
+    job.log("the agent is deployed");
+    Ok(())
+}
@@ -0,0 +1,195 @@
+use crate::libs::db_driver::DbDriver;
+use crate::libs::ssh_session;
+use crate::models::server::Server;
+use chrono::{NaiveDateTime, Utc};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+/// only the latest runs are kept in memory
+const MAX_RUNS: usize = 64;
+
+/// runs the same command on many servers in the background,
+/// the results are collected per host and can be polled with [`FleetExec::get`]
+#[derive(Clone, Default)]
+pub struct FleetExec {
+    inner: Arc<Mutex<FleetExecInner>>,
+}
+
+#[derive(Default)]
+struct FleetExecInner {
+    runs: HashMap<String, FleetRun>,
+    order: VecDeque<String>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct FleetRun {
+    pub id: String,
+    pub command: String,
+    pub started_at: NaiveDateTime,
+    pub finished_at: Option<NaiveDateTime>,
+    /// servers that have not finished yet
+    pub pending: Vec<String>,
+    pub results: Vec<HostResult>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct HostResult {
+    pub server_id: String,
+    pub name: String,
+    pub ip: String,
+    pub exit_code: Option<u32>,
+    pub stdout: String,
+    pub stderr: String,
+    pub timed_out: bool,
+    pub duration_ms: u128,
+    /// set when the command could not be run at all (e.g. connection or authentication failures)
+    pub error: Option<String>,
+}
+
+pub struct FleetCommand {
+    pub command: String,
+    pub stdin: Option<String>,
+    pub timeout: Duration,
+    pub concurrency: usize,
+}
+
+impl FleetExec {
+    /// start running the command on the servers, returns the id of the run
+    pub fn start(
+        &self,
+        db_driver: DbDriver,
+        servers: Vec<Server>,
+        command: FleetCommand,
+    ) -> String {
+        let id = cuid2::create_id();
+        let run = FleetRun {
+            id: id.clone(),
+            command: command.command.clone(),
+            started_at: Utc::now().naive_utc(),
+            finished_at: None,
+            pending: servers.iter().map(|s| s.id.clone()).collect(),
+            results: vec![],
+        };
+        self.insert(run);
+
+        let slf = self.clone();
+        let run_id = id.clone();
+        tokio::spawn(async move {
+            let command = Arc::new(command);
+            let semaphore = Arc::new(Semaphore::new(command.concurrency.max(1)));
+            let mut set = JoinSet::new();
+            // task -> the result reported if the task panics
+            let mut tasks = HashMap::new();
+
+            for server in servers {
+                let semaphore = semaphore.clone();
+                let command = command.clone();
+                let db_driver = db_driver.clone();
+                let failed = host_result(&server);
+                let task = set.spawn(async move {
+                    let _permit = semaphore.acquire_owned().await;
+                    run_on_server(&db_driver, server, &command).await
+                });
+                tasks.insert(task.id(), failed);
+            }
+
+            while let Some(result) = set.join_next_with_id().await {
+                let result = match result {
+                    Ok((id, result)) => {
+                        tasks.remove(&id);
+                        result
+                    }
+                    Err(e) => {
+                        log::error!("fleet exec task failed: {e}");
+                        let Some(mut failed) = tasks.remove(&e.id()) else {
+                            continue;
+                        };
+                        failed.error = Some(format!("the task failed: {e}"));
+                        failed
+                    }
+                };
+                slf.update(&run_id, |run| {
+                    run.pending.retain(|p| !p.eq(&result.server_id));
+                    run.results.push(result);
+                });
+            }
+
+            slf.update(&run_id, |run| {
+                run.finished_at = Some(Utc::now().naive_utc());
+            });
+        });
+
+        id
+    }
+
+    pub fn get(&self, id: &str) -> Option<FleetRun> {
+        self.inner.lock().unwrap().runs.get(id).cloned()
+    }
+
+    fn insert(&self, run: FleetRun) {
+        let mut l = self.inner.lock().unwrap();
+        l.order.push_back(run.id.clone());
+        l.runs.insert(run.id.clone(), run);
+        while l.order.len() > MAX_RUNS {
+            if let Some(old) = l.order.pop_front() {
+                l.runs.remove(&old);
+            }
+        }
+    }
+
+    fn update(&self, id: &str, f: impl FnOnce(&mut FleetRun)) {
+        if let Some(run) = self.inner.lock().unwrap().runs.get_mut(id) {
+            f(run);
+        }
+    }
+}
+
+/// the result of the server before the command ran
+fn host_result(server: &Server) -> HostResult {
+    HostResult {
+        server_id: server.id.clone(),
+        name: server.name.clone(),
+        ip: server.ip.clone(),
+        exit_code: None,
+        stdout: String::default(),
+        stderr: String::default(),
+        timed_out: false,
+        duration_ms: 0,
+        error: None,
+    }
+}
+
+async fn run_on_server(db_driver: &DbDriver, server: Server, command: &FleetCommand) -> HostResult {
+    let instant = Instant::now();
+    let mut result = host_result(&server);
+
+    let output = async {
+        let mut ssh = ssh_session::connect(&server, db_driver).await?;
+        let output = ssh
+            .exec(
+                &command.command,
+                command.stdin.as_ref().map(|s| s.as_bytes()),
+                command.timeout,
+            )
+            .await;
+        let _ = ssh.close().await;
+        output
+    }
+    .await;
+
+    match output {
+        Ok(output) => {
+            result.exit_code = output.exit_code;
+            result.stdout = output.stdout;
+            result.stderr = output.stderr;
+            result.timed_out = output.timed_out;
+        }
+        Err(e) => result.error = Some(e.to_string()),
+    }
+    result.duration_ms = instant.elapsed().as_millis();
+    result
+}
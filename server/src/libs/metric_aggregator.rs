@@ -0,0 +1,199 @@
+use crate::models::server_metric::ServerMetric;
+use chrono::{NaiveDateTime, TimeDelta};
+use machine_info::SystemStatus;
+use serde::Serialize;
+
+#[derive(Serialize)]
+pub struct MetricSample {
+    pub time: NaiveDateTime,
+    pub system_status: SystemStatus,
+}
+
+#[derive(Serialize, Default, Clone, Copy)]
+pub struct Aggregate {
+    pub avg: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+#[derive(Serialize)]
+pub struct MetricBucket {
+    /// start of the bucket
+    pub time: NaiveDateTime,
+    pub samples: usize,
+    pub cpu: Aggregate,
+    pub memory: Aggregate,
+}
+
+/// the raw samples that have a status, oldest first
+pub fn samples(metrics: Vec<ServerMetric>) -> Vec<MetricSample> {
+    metrics
+        .into_iter()
+        .filter_map(|m| {
+            Some(MetricSample {
+                time: m.time,
+                system_status: m.system_status?,
+            })
+        })
+        .collect()
+}
+
+/// group the samples into `step` wide buckets starting at `from` and calculate avg/min/max of each bucket.
+///
+/// empty buckets are skipped, `metrics` must be sorted by time
+pub fn downsample(
+    metrics: &[ServerMetric],
+    from: NaiveDateTime,
+    step: TimeDelta,
+) -> Vec<MetricBucket> {
+    let step_ms = step.num_milliseconds().max(1);
+    let mut buckets: Vec<MetricBucket> = vec![];
+    let mut cpu = Accumulator::default();
+    let mut memory = Accumulator::default();
+
+    for metric in metrics {
+        let Some(status) = &metric.system_status else {
+            continue;
+        };
+        let index = (metric.time - from).num_milliseconds().max(0) / step_ms;
+        let bucket_start = from + TimeDelta::milliseconds(index * step_ms);
+
+        if buckets.last().is_none_or(|b| b.time != bucket_start) {
+            if let Some(last) = buckets.last_mut() {
+                last.cpu = cpu.take();
+                last.memory = memory.take();
+            }
+            buckets.push(MetricBucket {
+                time: bucket_start,
+                samples: 0,
+                cpu: Aggregate::default(),
+                memory: Aggregate::default(),
+            });
+        }
+
+        let bucket = buckets.last_mut().unwrap();
+        bucket.samples += 1;
+        cpu.push(status.cpu as f64);
+        memory.push(status.memory as f64);
+    }
+
+    if let Some(last) = buckets.last_mut() {
+        last.cpu = cpu.take();
+        last.memory = memory.take();
+    }
+    buckets
+}
+
+#[derive(Default)]
+struct Accumulator {
+    sum: f64,
+    count: usize,
+    min: f64,
+    max: f64,
+}
+
+impl Accumulator {
+    fn push(&mut self, value: f64) {
+        if self.count == 0 {
+            self.min = value;
+            self.max = value;
+        } else {
+            self.min = self.min.min(value);
+            self.max = self.max.max(value);
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    fn take(&mut self) -> Aggregate {
+        let acc = std::mem::take(self);
+        if acc.count == 0 {
+            return Aggregate::default();
+        }
+        Aggregate {
+            avg: acc.sum / acc.count as f64,
+            min: acc.min,
+            max: acc.max,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::DateTime;
+    use machine_info::Machine;
+
+    fn from() -> NaiveDateTime {
+        DateTime::from_timestamp(1_700_000_000, 0)
+            .unwrap()
+            .naive_utc()
+    }
+
+    fn metric(secs: i64, status: Option<(i64, i64)>) -> ServerMetric {
+        ServerMetric {
+            server_id: "a".into(),
+            time: from() + TimeDelta::seconds(secs),
+            system_info: Machine::new().system_info(),
+            system_status: status.map(|(cpu, memory)| SystemStatus {
+                cpu: cpu as _,
+                memory: memory as _,
+            }),
+        }
+    }
+
+    #[test]
+    fn aggregates_every_bucket() {
+        let metrics = [
+            metric(0, Some((10, 100))),
+            metric(30, Some((30, 300))),
+            metric(60, Some((50, 500))),
+        ];
+        let buckets = downsample(&metrics, from(), TimeDelta::seconds(60));
+
+        assert_eq!(buckets.len(), 2);
+        assert_eq!(buckets[0].time, from());
+        assert_eq!(buckets[0].samples, 2);
+        assert_eq!(buckets[0].cpu.avg, 20.0);
+        assert_eq!(buckets[0].cpu.min, 10.0);
+        assert_eq!(buckets[0].cpu.max, 30.0);
+        assert_eq!(buckets[0].memory.avg, 200.0);
+        assert_eq!(buckets[1].time, from() + TimeDelta::seconds(60));
+        assert_eq!(buckets[1].samples, 1);
+        assert_eq!(buckets[1].cpu.avg, 50.0);
+        assert_eq!(buckets[1].memory.max, 500.0);
+    }
+
+    #[test]
+    fn skips_the_empty_buckets() {
+        let metrics = [metric(0, Some((10, 100))), metric(185, Some((20, 200)))];
+        let buckets = downsample(&metrics, from(), TimeDelta::seconds(60));
+
+        let times = buckets.iter().map(|b| b.time).collect::<Vec<_>>();
+        assert_eq!(times, [from(), from() + TimeDelta::seconds(180)]);
+    }
+
+    #[test]
+    fn skips_the_samples_without_a_status() {
+        let metrics = [
+            metric(0, None),
+            metric(60, Some((10, 100))),
+            metric(120, None),
+        ];
+        let buckets = downsample(&metrics, from(), TimeDelta::seconds(60));
+
+        assert_eq!(buckets.len(), 1);
+        assert_eq!(buckets[0].time, from() + TimeDelta::seconds(60));
+        assert!(downsample(&[], from(), TimeDelta::seconds(60)).is_empty());
+    }
+
+    #[test]
+    fn puts_the_samples_before_from_into_the_first_bucket() {
+        let metrics = [metric(-30, Some((10, 100))), metric(10, Some((30, 300)))];
+        let buckets = downsample(&metrics, from(), TimeDelta::seconds(60));
+
+        assert_eq!(buckets.len(), 1);
+        assert_eq!(buckets[0].time, from());
+        assert_eq!(buckets[0].samples, 2);
+    }
+}
@@ -0,0 +1,22 @@
+use crate::libs::shared_state::SharedState;
+use chrono::{TimeDelta, Utc};
+use std::time::Duration;
+
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// periodically removes the samples that are older than `metrics_retention_hours`
+pub fn run(state: SharedState) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+        loop {
+            interval.tick().await;
+            let retention = TimeDelta::hours(state.app_config.metrics_retention_hours as i64);
+            let before = Utc::now().naive_utc() - retention;
+            match state.db_driver.prune_metrics(before) {
+                Ok(0) => {}
+                Ok(removed) => log::info!("pruned {removed} metrics older than {before} UTC"),
+                Err(e) => log::error!("failed to prune metrics: {e}"),
+            }
+        }
+    });
+}
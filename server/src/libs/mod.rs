@@ -1,27 +1,46 @@
 use jsonwebtoken::{encode, EncodingKey, Header};
 use serde::{Deserialize, Serialize};
 
+pub mod agent_bundle;
+pub mod agent_credentials;
+pub mod agent_hub;
+pub mod agent_pki;
+pub mod alert_engine;
 pub mod agent_service;
+pub mod agent_update;
 pub mod api_response;
 pub mod app_config;
+pub mod audit;
+pub mod auth;
 pub mod db_driver;
+pub mod deploy_jobs;
+pub mod fleet_exec;
+pub mod metric_aggregator;
+pub mod metric_retention;
+pub mod notifier;
 pub mod rmp_serializer;
+pub mod secret_box;
 pub mod shared_state;
 pub mod ssh_session;
 
+/// claims of the agent tokens, the tokens don't expire but can be revoked, see [`agent_credentials`]
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct TokenClaims {
     pub sub: String,
     pub iat: usize,
     pub exp: usize,
+    /// empty for the tokens issued before the agent credentials existed
+    #[serde(default)]
+    pub jti: String,
 }
 
-pub(crate) fn create_jwt_token(secret: &str, sub: &String) -> String {
+pub(crate) fn create_jwt_token(secret: &str, sub: &String, jti: &str) -> String {
     let now = chrono::Utc::now();
     let claims = TokenClaims {
         sub: sub.to_owned(),
         iat: now.timestamp() as usize,
         exp: 0,
+        jti: jti.to_owned(),
     };
 
     encode(
@@ -0,0 +1,471 @@
+use crate::libs::db_driver::DbDriver;
+use crate::models::notification_channel::{ChannelKind, NotificationChannel, SmtpTls};
+use crate::prelude::Res;
+use chrono::{NaiveDateTime, Utc};
+use eyre::eyre;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use serde::Serialize;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+const MAX_ATTEMPTS: u32 = 3;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Notification {
+    pub title: String,
+    pub message: String,
+    /// e.g. `firing`, `resolved`, `offline`
+    pub status: String,
+    pub server_id: Option<String>,
+    pub server_name: Option<String>,
+    pub value: Option<f64>,
+    pub time: NaiveDateTime,
+}
+
+/// delivers notifications to the configured channels in the background
+#[derive(Clone)]
+pub struct Notifier {
+    db_driver: DbDriver,
+    http: reqwest::Client,
+    /// notifications are also sent from the sub-server io thread which is not part of the runtime
+    runtime: Handle,
+}
+
+impl Notifier {
+    /// must be called inside the tokio runtime
+    pub fn new(db_driver: DbDriver) -> Self {
+        Self {
+            db_driver,
+            http: reqwest::Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .build()
+                .unwrap_or_default(),
+            runtime: Handle::current(),
+        }
+    }
+
+    /// send the notification to the given channels
+    pub fn notify(&self, channel_ids: &[String], notification: Notification) {
+        if channel_ids.is_empty() {
+            return;
+        }
+        self.notify_where(notification, |c| channel_ids.contains(&c.id));
+    }
+
+    /// send the notification to every channel that subscribed to agent events
+    pub fn notify_agent_event(&self, notification: Notification) {
+        self.notify_where(notification, |c| c.agent_events);
+    }
+
+    fn notify_where(
+        &self,
+        notification: Notification,
+        filter: impl Fn(&NotificationChannel) -> bool,
+    ) {
+        let channels = match self.db_driver.all_notification_channels() {
+            Ok(channels) => channels,
+            Err(e) => {
+                log::error!("failed to load the notification channels: {e}");
+                return;
+            }
+        };
+
+        for channel in channels.into_iter().filter(|c| c.enabled && filter(c)) {
+            let slf = self.clone();
+            let notification = notification.clone();
+            self.runtime.spawn(async move {
+                if let Err(e) = slf.send(&channel, &notification).await {
+                    log::error!("failed to notify `{}`: {e}", channel.name);
+                }
+            });
+        }
+    }
+
+    /// deliver the notification to a single channel, webhooks and http requests are retried
+    pub async fn send(&self, channel: &NotificationChannel, notification: &Notification) -> Res {
+        match &channel.kind {
+            ChannelKind::Webhook { url, headers } => {
+                let body = serde_json::to_string(notification)?;
+                let mut headers = headers.clone();
+                headers
+                    .entry("content-type".into())
+                    .or_insert("application/json".into());
+                self.send_with_retries("POST", url, &headers, body).await
+            }
+            ChannelKind::Http {
+                method,
+                url,
+                headers,
+                body_template,
+            } => {
+                let body = render(body_template, notification)?;
+                self.send_with_retries(method, url, headers, body).await
+            }
+            ChannelKind::Email {
+                host,
+                port,
+                username,
+                tls,
+                from,
+                to,
+            } => {
+                let password = match &channel.secret {
+                    Some(secret) => Some(secret.open::<String>()?),
+                    None => None,
+                };
+                send_email(
+                    host,
+                    *port,
+                    username,
+                    password,
+                    *tls,
+                    from,
+                    to,
+                    notification,
+                )
+                .await
+            }
+        }
+    }
+
+    async fn send_with_retries(
+        &self,
+        method: &str,
+        url: &str,
+        headers: &std::collections::HashMap<String, String>,
+        body: String,
+    ) -> Res {
+        let method = reqwest::Method::from_str(&method.to_uppercase())?;
+        let mut last_error = eyre!("no attempt was made");
+        for attempt in 1..=MAX_ATTEMPTS {
+            let mut request = self.http.request(method.clone(), url).body(body.clone());
+            for (name, value) in headers {
+                request = request.header(name, value);
+            }
+
+            match request.send().await {
+                Ok(response) if response.status().is_success() => return Ok(()),
+                Ok(response) => last_error = eyre!("{url} responded with {}", response.status()),
+                Err(e) => last_error = e.into(),
+            }
+
+            if attempt < MAX_ATTEMPTS {
+                log::warn!("notification attempt {attempt} to {url} failed: {last_error}");
+                tokio::time::sleep(Duration::from_secs(2u64.pow(attempt - 1))).await;
+            }
+        }
+        Err(last_error)
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn send_email(
+    host: &str,
+    port: u16,
+    username: &Option<String>,
+    password: Option<String>,
+    tls: SmtpTls,
+    from: &str,
+    to: &[String],
+    notification: &Notification,
+) -> Res {
+    let mut message = Message::builder()
+        .from(Mailbox::from_str(from)?)
+        .subject(&notification.title);
+    for to in to {
+        message = message.to(Mailbox::from_str(to)?);
+    }
+    let message = message.body(notification.message.clone())?;
+
+    let mut transport = match tls {
+        SmtpTls::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host),
+        SmtpTls::StartTls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)?,
+        SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(host)?,
+    }
+    .port(port)
+    .timeout(Some(REQUEST_TIMEOUT));
+
+    if let Some(username) = username {
+        transport = transport.credentials(Credentials::new(
+            username.to_owned(),
+            password.unwrap_or_default(),
+        ));
+    }
+
+    transport.build().send(message).await?;
+    Ok(())
+}
+
+/// the placeholders are json escaped so they can be used inside the strings of a json body,
+/// `{{raw.name}}` inserts the value as is.
+///
+/// the template is read in a single pass, so placeholders inside the values are not replaced
+fn render(template: &str, notification: &Notification) -> eyre::Result<String> {
+    let value = notification
+        .value
+        .map(|v| format!("{v:.2}"))
+        .unwrap_or_default();
+    let server = notification
+        .server_name
+        .as_ref()
+        .or(notification.server_id.as_ref())
+        .cloned()
+        .unwrap_or_default();
+    let placeholder = |name: &str| -> eyre::Result<Option<String>> {
+        let (name, raw) = match name.strip_prefix("raw.") {
+            Some(name) => (name, true),
+            None => (name, false),
+        };
+        let value = match name {
+            "json" if !raw => return Ok(Some(serde_json::to_string(notification)?)),
+            "title" => notification.title.clone(),
+            "message" => notification.message.clone(),
+            "status" => notification.status.clone(),
+            "server" => server.clone(),
+            "value" => value.clone(),
+            "time" => notification.time.to_string(),
+            _ => return Ok(None),
+        };
+        Ok(Some(if raw { value } else { json_escape(&value)? }))
+    };
+
+    let mut body = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let Some(len) = rest[start..].find("}}").map(|end| end + 2) else {
+            break;
+        };
+        body.push_str(&rest[..start]);
+        let token = &rest[start..start + len];
+        match placeholder(&token[2..len - 2])? {
+            Some(value) => body.push_str(&value),
+            // unknown placeholders are kept
+            None => body.push_str(token),
+        }
+        rest = &rest[start + len..];
+    }
+    body.push_str(rest);
+    Ok(body)
+}
+
+/// the value as the content of a json string, without the quotes
+fn json_escape(value: &str) -> eyre::Result<String> {
+    let quoted = serde_json::to_string(value)?;
+    Ok(quoted[1..quoted.len() - 1].to_owned())
+}
+
+impl Notification {
+    pub fn test() -> Self {
+        Self {
+            title: "manage-rs test notification".into(),
+            message: "this is a test notification, the channel is configured correctly".into(),
+            status: "test".into(),
+            server_id: None,
+            server_name: None,
+            value: None,
+            time: Utc::now().naive_utc(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::State;
+    use axum::http::StatusCode;
+    use axum::routing::post;
+    use axum::Router;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    /// what the stand-in servers received, in order
+    type Received = Arc<Mutex<Vec<String>>>;
+
+    fn notifier() -> Notifier {
+        let path = std::env::temp_dir().join(format!("notifier-{}.db", cuid2::create_id()));
+        Notifier::new(DbDriver::new(path.to_str().unwrap()).unwrap())
+    }
+
+    fn channel(kind: ChannelKind) -> NotificationChannel {
+        NotificationChannel {
+            id: cuid2::create_id(),
+            name: "stand-in".into(),
+            kind,
+            secret: None,
+            agent_events: false,
+            enabled: true,
+        }
+    }
+
+    fn notification() -> Notification {
+        Notification {
+            title: r#"cpu is "high""#.into(),
+            message: r"C:\ is full {{title}}".into(),
+            status: "firing".into(),
+            server_id: Some("abc".into()),
+            server_name: Some("web \"1\"".into()),
+            value: Some(93.456),
+            time: Utc::now().naive_utc(),
+        }
+    }
+
+    /// an http server that fails the first `failures` requests
+    async fn http_stand_in(failures: usize) -> (String, Received) {
+        let received = Received::default();
+        let router = Router::new()
+            .route(
+                "/",
+                post(
+                    move |State(received): State<Received>, body: String| async move {
+                        let mut received = received.lock().unwrap();
+                        received.push(body);
+                        if received.len() <= failures {
+                            StatusCode::INTERNAL_SERVER_ERROR
+                        } else {
+                            StatusCode::OK
+                        }
+                    },
+                ),
+            )
+            .with_state(received.clone());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        (url, received)
+    }
+
+    /// an smtp server that accepts every mail, only speaks enough of the protocol for lettre
+    async fn smtp_stand_in() -> (u16, Received) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let received = Received::default();
+        let received_cl = received.clone();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (read, mut write) = stream.into_split();
+            let mut lines = BufReader::new(read).lines();
+            write.write_all(b"220 stand-in\r\n").await.unwrap();
+            let mut data: Option<String> = None;
+            while let Ok(Some(line)) = lines.next_line().await {
+                if data.is_some() {
+                    if line == "." {
+                        received_cl.lock().unwrap().push(data.take().unwrap());
+                        write.write_all(b"250 queued\r\n").await.unwrap();
+                    } else if let Some(data) = data.as_mut() {
+                        data.push_str(&line);
+                        data.push('\n');
+                    }
+                    continue;
+                }
+                let command = line.split_whitespace().next().unwrap_or_default();
+                let reply: &[u8] = match command.to_uppercase().as_str() {
+                    "DATA" => {
+                        data = Some(String::new());
+                        b"354 go ahead\r\n"
+                    }
+                    "QUIT" => b"221 bye\r\n",
+                    _ => b"250 ok\r\n",
+                };
+                write.write_all(reply).await.unwrap();
+            }
+        });
+        (port, received)
+    }
+
+    #[test]
+    fn render_escapes_the_placeholders() {
+        let notification = notification();
+        let body = render(
+            r#"{"text":"{{title}} on {{server}}","body":"{{message}}","value":"{{value}}"}"#,
+            &notification,
+        )
+        .unwrap();
+        let body = serde_json::from_str::<serde_json::Value>(&body).unwrap();
+        assert_eq!(body["text"], r#"cpu is "high" on web "1""#);
+        // placeholders inside the values are kept as they are
+        assert_eq!(body["body"], notification.message);
+        assert_eq!(body["value"], "93.46");
+    }
+
+    #[test]
+    fn render_inserts_raw_and_json_placeholders() {
+        let notification = notification();
+        let body = render("{{raw.title}}|{{unknown}}|{{json}}", &notification).unwrap();
+        let (raw, rest) = body.split_once('|').unwrap();
+        let (unknown, json) = rest.split_once('|').unwrap();
+        assert_eq!(raw, notification.title);
+        assert_eq!(unknown, "{{unknown}}");
+        let json = serde_json::from_str::<serde_json::Value>(json).unwrap();
+        assert_eq!(json["status"], "firing");
+    }
+
+    #[tokio::test]
+    async fn http_channel_sends_the_rendered_body() {
+        let (url, received) = http_stand_in(0).await;
+        let channel = channel(ChannelKind::Http {
+            method: "post".into(),
+            url,
+            headers: HashMap::new(),
+            body_template: r#"{"text":"{{title}}"}"#.into(),
+        });
+        notifier().send(&channel, &notification()).await.unwrap();
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 1);
+        let body = serde_json::from_str::<serde_json::Value>(&received[0]).unwrap();
+        assert_eq!(body["text"], notification().title);
+    }
+
+    #[tokio::test]
+    async fn webhook_is_retried_until_it_succeeds() {
+        let (url, received) = http_stand_in(2).await;
+        let channel = channel(ChannelKind::Webhook {
+            url,
+            headers: HashMap::new(),
+        });
+        notifier().send(&channel, &notification()).await.unwrap();
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 3);
+        assert!(received.iter().all(|b| b.contains(r#""status":"firing""#)));
+    }
+
+    #[tokio::test]
+    async fn webhook_gives_up_after_the_last_attempt() {
+        let (url, received) = http_stand_in(usize::MAX).await;
+        let channel = channel(ChannelKind::Webhook {
+            url,
+            headers: HashMap::new(),
+        });
+        let result = notifier().send(&channel, &notification()).await;
+
+        assert!(result.is_err());
+        assert_eq!(received.lock().unwrap().len(), MAX_ATTEMPTS as usize);
+    }
+
+    #[tokio::test]
+    async fn email_is_delivered_to_the_smtp_server() {
+        let (port, received) = smtp_stand_in().await;
+        let channel = channel(ChannelKind::Email {
+            host: "127.0.0.1".into(),
+            port,
+            username: None,
+            tls: SmtpTls::None,
+            from: "alerts@example.com".into(),
+            to: vec!["ops@example.com".into()],
+        });
+        let mut notification = notification();
+        notification.message = "the disk of web-1 is full".into();
+        notifier().send(&channel, &notification).await.unwrap();
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 1);
+        assert!(received[0].contains("the disk of web-1 is full"));
+    }
+}
@@ -0,0 +1,101 @@
+use crate::prelude::Res;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use eyre::eyre;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::OnceLock;
+use tokio::io::AsyncWriteExt;
+
+static MASTER_KEY: OnceLock<Key> = OnceLock::new();
+
+/// a secret (e.g. [`ServerSecret`](crate::models::server::ServerSecret)) encrypted with the master key,
+/// this is what gets stored in the db
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SealedSecret {
+    nonce: Vec<u8>,
+    ciphertext: Vec<u8>,
+}
+
+/// load the master key from `path`, a new random key is generated if the file doesn't exist.
+///
+/// must be called before opening the db
+pub async fn init(path: &str) -> Res {
+    let path = Path::new(path);
+    let key = if tokio::fs::try_exists(path).await? {
+        let bytes = tokio::fs::read(path).await?;
+        if bytes.len() != 32 {
+            return Err(eyre!("the master key at {path:?} is corrupted"));
+        }
+        *Key::from_slice(&bytes)
+    } else {
+        log::info!("generating a new master key -> {path:?}");
+        let key = XChaCha20Poly1305::generate_key(&mut OsRng);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        write_owner_only(path, key.as_slice()).await?;
+        key
+    };
+
+    MASTER_KEY
+        .set(key)
+        .map_err(|_| eyre!("master key is already initialized"))
+}
+
+/// write the file with `0600` from the start, so it is never readable by others
+pub(crate) async fn write_owner_only(path: &Path, content: &[u8]) -> Res {
+    let mut options = tokio::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(path).await?;
+    file.write_all(content).await?;
+    file.flush().await?;
+    // the mode only applies to new files
+    set_owner_only(path).await
+}
+
+#[cfg(unix)]
+pub(crate) async fn set_owner_only(path: &Path) -> Res {
+    use std::os::unix::fs::PermissionsExt;
+    tokio::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600)).await?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub(crate) async fn set_owner_only(_path: &Path) -> Res {
+    Ok(())
+}
+
+fn cipher() -> eyre::Result<XChaCha20Poly1305> {
+    let key = MASTER_KEY
+        .get()
+        .ok_or(eyre!("master key is not initialized"))?;
+    Ok(XChaCha20Poly1305::new(key))
+}
+
+impl SealedSecret {
+    pub fn seal<T: Serialize>(secret: &T) -> eyre::Result<Self> {
+        let plaintext = rmp_serde::encode::to_vec(secret)?;
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = cipher()?
+            .encrypt(&nonce, plaintext.as_slice())
+            .map_err(|_| eyre!("failed to encrypt the secret"))?;
+        Ok(Self {
+            nonce: nonce.to_vec(),
+            ciphertext,
+        })
+    }
+
+    pub fn open<T: DeserializeOwned>(&self) -> eyre::Result<T> {
+        if self.nonce.len() != 24 {
+            return Err(eyre!("the secret is not sealed"));
+        }
+        let plaintext = cipher()?
+            .decrypt(XNonce::from_slice(&self.nonce), self.ciphertext.as_slice())
+            .map_err(|_| eyre!("failed to decrypt the secret, is the master key correct?"))?;
+        Ok(rmp_serde::decode::from_slice(&plaintext)?)
+    }
+}
@@ -1,5 +1,9 @@
+use crate::libs::agent_hub::AgentHub;
+use crate::libs::agent_pki::AgentPki;
 use crate::libs::app_config::AppConfigRef;
 use crate::libs::db_driver::DbDriver;
+use crate::libs::fleet_exec::FleetExec;
+use crate::libs::notifier::Notifier;
 use std::sync::Arc;
 use crate::libs::agent_service::AgentService;
 
@@ -14,15 +18,28 @@ pub struct SharedStateInner {
     pub app_config: AppConfigRef,
     pub db_driver: DbDriver,
     pub agent_service: AgentService,
+    pub fleet_exec: FleetExec,
+    pub agent_hub: AgentHub,
+    pub notifier: Notifier,
+    pub agent_pki: AgentPki,
 }
 
 impl SharedState {
-    pub async fn new(config: AppConfigRef, agent_service: AgentService) -> Self {
+    pub async fn new(
+        config: AppConfigRef,
+        db_driver: DbDriver,
+        agent_service: AgentService,
+        agent_pki: AgentPki,
+    ) -> Self {
         Self {
             inner: Arc::new(SharedStateInner {
                 agent_service,
-                db_driver: DbDriver::new(&config.db_path),
+                notifier: Notifier::new(db_driver.clone()),
+                db_driver,
                 app_config: config,
+                fleet_exec: FleetExec::default(),
+                agent_hub: AgentHub::default(),
+                agent_pki,
             }),
         }
     }
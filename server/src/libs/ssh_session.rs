@@ -1,51 +1,218 @@
 use std::borrow::Cow;
+use std::fmt::{Display, Formatter};
 use std::future::Future;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::ops::Deref;
 use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Duration;
-use crate::models::server::{Server, ServerSecret};
+use crate::libs::db_driver::DbDriver;
+use crate::models::known_host::KnownHost;
+use crate::models::server::{Escalation, Server, ServerSecret};
 use crate::prelude::Res;
+use chrono::Utc;
 use eyre::eyre;
 use russh::keys::*;
 use russh::*;
 use russh_sftp::client::SftpSession;
-use tokio::io::AsyncWriteExt;
+use serde::Serialize;
 
-pub async fn connect(server: &Server) -> eyre::Result<SshSession> {
-    let ServerSecret::Pwd(pwd) = &server.secret else {
-        return Err(eyre!("ssh keys are not supported yet!"));
+/// connect to the server and verify its host key against the pinned one.
+///
+/// the key is pinned on the first successful connection (trust on first use),
+/// later connections with a different key are rejected.
+pub async fn connect(server: &Server, db_driver: &DbDriver) -> eyre::Result<SshSession> {
+    // the only place where the secret is decrypted
+    let secret = server.secret.open::<ServerSecret>()?;
+    let auth = match &secret {
+        ServerSecret::Pwd(pwd) => SshAuth::Password(pwd),
+        ServerSecret::SshKey {
+            key, passphrase, ..
+        } => {
+            let key = decode_secret_key(key, passphrase.as_deref())
+                .map_err(|e| eyre!("failed to load the private key of {}: {e}", server.ip))?;
+            SshAuth::PrivateKey(key)
+        }
     };
 
-    SshSession::connect(&server.user, pwd, &server.ip, server.port).await
+    let known_host = db_driver.get_known_host(&server.id)?;
+    let expected = known_host.as_ref().map(|k| k.fingerprint.as_str());
+    let mut session = SshSession::connect(&server.user, auth, &server.ip, server.port, expected)
+        .await
+        .map_err(|e| match e.downcast_ref::<HostKeyMismatch>() {
+            Some(_) => eyre!(
+                "{e}, if this change is expected verify the new key and accept its fingerprint via `PUT /servers/{}/host-key`",
+                server.id
+            ),
+            None => e,
+        })?;
+
+    if known_host.is_none() {
+        log::info!(
+            "pinning the host key of {}: {}",
+            server.ip,
+            session.host_key.fingerprint
+        );
+        db_driver.set_known_host(session.host_key.to_known_host(&server.id))?;
+    }
+    session.escalation = server.escalation;
+    session.sudo_password = secret.sudo_password().map(|p| p.to_owned());
+    Ok(session)
+}
+
+/// connect to the server without authenticating and return the host key it presents
+pub async fn fetch_host_key(host: &str, port: usize) -> eyre::Result<HostKey> {
+    let (mut session, seen) = SshSession::handshake(host, port, None).await?;
+    session
+        .disconnect(Disconnect::ByApplication, "", "English")
+        .await?;
+    let host_key = seen.lock().unwrap().take();
+    host_key.ok_or(eyre!("{host} did not present a host key"))
+}
+
+pub enum SshAuth<'a> {
+    Password(&'a str),
+    PrivateKey(PrivateKey),
+}
+
+#[derive(Debug, Clone)]
+pub struct HostKey {
+    pub fingerprint: String,
+    pub algorithm: String,
+}
+
+impl HostKey {
+    pub fn to_known_host(&self, server_id: &str) -> KnownHost {
+        KnownHost {
+            server_id: server_id.to_owned(),
+            fingerprint: self.fingerprint.clone(),
+            algorithm: self.algorithm.clone(),
+            accepted_at: Utc::now().naive_utc(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct HostKeyMismatch {
+    host: SocketAddr,
+    expected: String,
+    actual: String,
+}
+
+impl Display for HostKeyMismatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "the host key of {} has changed (expected {}, got {}), the connection might be intercepted",
+            self.host, self.expected, self.actual
+        )
+    }
+}
+
+impl std::error::Error for HostKeyMismatch {}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExecOutput {
+    pub stdout: String,
+    pub stderr: String,
+    pub exit_code: Option<u32>,
+    pub timed_out: bool,
 }
 
 pub struct SshSession {
     session: client::Handle<SshClient>,
+    pub host_key: HostKey,
+    /// used by [`SshSession::exec_privileged`]
+    escalation: Escalation,
+    sudo_password: Option<String>,
 }
 
-struct SshClient;
+struct SshClient {
+    expected_fingerprint: Option<String>,
+    seen: Arc<std::sync::Mutex<Option<HostKey>>>,
+}
 
 impl client::Handler for SshClient {
     type Error = russh::Error;
 
     async fn check_server_key(
         &mut self,
-        _server_public_key: &ssh_key::PublicKey,
+        server_public_key: &ssh_key::PublicKey,
     ) -> Result<bool, Self::Error> {
-        Ok(true)
+        let fingerprint = server_public_key.fingerprint(HashAlg::Sha256).to_string();
+        let trusted = self
+            .expected_fingerprint
+            .as_ref()
+            .is_none_or(|expected| expected.eq(&fingerprint));
+
+        *self.seen.lock().unwrap() = Some(HostKey {
+            fingerprint,
+            algorithm: server_public_key.algorithm().to_string(),
+        });
+        Ok(trusted)
     }
 }
 impl SshSession {
+    /// `expected_fingerprint` is the pinned host key, if it is `None` any key will be accepted
     pub async fn connect(
-        user: &String,
-        pwd: &String,
+        user: &str,
+        auth: SshAuth<'_>,
         host: &str,
         port: usize,
+        expected_fingerprint: Option<&str>,
     ) -> eyre::Result<Self> {
-        let addrs = SocketAddr::from((IpAddr::V4(Ipv4Addr::from_str(host)?), port as u16));
-        log::info!("connecting to {addrs} : {user} + {pwd}");
+        let (mut session, seen) = Self::handshake(host, port, expected_fingerprint).await?;
+        let addrs = session_addr(host, port)?;
+
+        let auth_res = match auth {
+            SshAuth::Password(pwd) => session.authenticate_password(user, pwd).await?,
+            SshAuth::PrivateKey(key) => {
+                // rsa keys need the strongest signature hash the server supports (rsa-sha2-*),
+                // other key types ignore this
+                let hash_alg = if matches!(key.algorithm(), Algorithm::Rsa { .. }) {
+                    session.best_supported_rsa_hash().await?.flatten()
+                } else {
+                    None
+                };
+                let key = PrivateKeyWithHashAlg::new(Arc::new(key), hash_alg);
+                session.authenticate_publickey(user, key).await?
+            }
+        };
+
+        if !auth_res.success() {
+            return Err(eyre!("authentication failed for {user}@{addrs}"));
+        }
+
+        let host_key = seen
+            .lock()
+            .unwrap()
+            .take()
+            .ok_or(eyre!("{addrs} did not present a host key"))?;
+        Ok(Self {
+            session,
+            host_key,
+            escalation: Escalation::None,
+            sudo_password: None,
+        })
+    }
+
+    async fn handshake(
+        host: &str,
+        port: usize,
+        expected_fingerprint: Option<&str>,
+    ) -> eyre::Result<(
+        client::Handle<SshClient>,
+        Arc<std::sync::Mutex<Option<HostKey>>>,
+    )> {
+        let addrs = session_addr(host, port)?;
+        log::info!("connecting to {addrs}");
         let config = client::Config {
             inactivity_timeout: Some(Duration::from_secs(5)),
             preferred: Preferred {
@@ -59,17 +226,31 @@ impl SshSession {
         };
 
         let config = Arc::new(config);
-        let sh = SshClient {};
-
-        let mut session = client::connect(config, addrs, sh).await?;
-
-        let auth_res = session.authenticate_password(user, pwd).await?;
-
-        if !auth_res.success() {
-            return Err(eyre!("Authentication (with publickey) failed"));
-        }
+        let seen = Arc::new(std::sync::Mutex::new(None));
+        let sh = SshClient {
+            expected_fingerprint: expected_fingerprint.map(|f| f.to_owned()),
+            seen: seen.clone(),
+        };
 
-        Ok(Self { session })
+        let session = match client::connect(config, addrs, sh).await {
+            Ok(session) => session,
+            Err(russh::Error::UnknownKey) => {
+                let actual = seen
+                    .lock()
+                    .unwrap()
+                    .take()
+                    .map(|k| k.fingerprint)
+                    .unwrap_or_default();
+                return Err(HostKeyMismatch {
+                    host: addrs,
+                    expected: expected_fingerprint.unwrap_or_default().to_owned(),
+                    actual,
+                }
+                .into());
+            }
+            Err(e) => return Err(e.into()),
+        };
+        Ok((session, seen))
     }
 
     pub async fn call_capture_output(&mut self, command: &str) -> eyre::Result<String> {
@@ -77,10 +258,12 @@ impl SshSession {
         {
             let output = Arc::new(std::sync::RwLock::new(vec![]));
             let output_cl = output.clone();
-            self.call(command, move |data| {
+            self.call(command, move |stream, data| {
                 let output_cl = output_cl.clone();
                 async move {
-                    output_cl.clone().write().unwrap().extend(data.iter());
+                    if let OutputStream::Stdout = stream {
+                        output_cl.clone().write().unwrap().extend(data.iter());
+                    }
                     Ok(())
                 }
             })
@@ -92,25 +275,89 @@ impl SshSession {
         Ok(output_str)
     }
 
-    pub async fn call_with_stdout(&mut self, command: &str) -> Res {
-        self.call(command, move |data| async move {
-            let mut stdout = tokio::io::stdout();
-            stdout.write_all(&data).await?;
-            stdout.flush().await?;
-            Ok(())
+    /// run the command and collect stdout and stderr separately.
+    ///
+    /// if the command doesn't finish within `timeout` the output that was received so far is returned with `timed_out` set
+    pub async fn exec(
+        &mut self,
+        command: &str,
+        stdin: Option<&[u8]>,
+        timeout: Duration,
+    ) -> eyre::Result<ExecOutput> {
+        let stdout = Arc::new(std::sync::Mutex::new(vec![]));
+        let stderr = Arc::new(std::sync::Mutex::new(vec![]));
+        let (stdout_cl, stderr_cl) = (stdout.clone(), stderr.clone());
+
+        let call = self.call_with_stdin(command, stdin, move |stream, data| {
+            let output = match stream {
+                OutputStream::Stdout => stdout_cl.clone(),
+                OutputStream::Stderr => stderr_cl.clone(),
+            };
+            async move {
+                output.lock().unwrap().extend(data.iter());
+                Ok(())
+            }
+        });
+
+        let (exit_code, timed_out) = match tokio::time::timeout(timeout, call).await {
+            Ok(code) => (code?, false),
+            Err(_) => (None, true),
+        };
+
+        let stdout = String::from_utf8_lossy(&stdout.lock().unwrap()).to_string();
+        let stderr = String::from_utf8_lossy(&stderr.lock().unwrap()).to_string();
+        Ok(ExecOutput {
+            stdout,
+            stderr,
+            exit_code,
+            timed_out,
         })
-        .await?;
-        Ok(())
     }
 
+    /// run the command as root with the escalation of the server, see [`Escalation`]
+    pub async fn exec_privileged(
+        &mut self,
+        command: &str,
+        timeout: Duration,
+    ) -> eyre::Result<ExecOutput> {
+        let (command, stdin) = match self.escalation {
+            Escalation::None => (command.to_owned(), None),
+            Escalation::Sudo => {
+                let pwd = self.sudo_password.as_ref().ok_or(eyre!(
+                    "sudo needs a password, set `sudo_password` in the secret"
+                ))?;
+                // sudo reads the password from stdin, the empty prompt keeps it out of the output
+                (format!("sudo -S -p '' {command}"), Some(format!("{pwd}\n")))
+            }
+            Escalation::SudoNopasswd => (format!("sudo -n {command}"), None),
+        };
+        self.exec(&command, stdin.as_deref().map(str::as_bytes), timeout)
+            .await
+    }
+
+    /// returns the exit code of the command, `None` if it did not exit cleanly (e.g. killed by a signal)
     pub async fn call<F: Future<Output = Res>>(
         &mut self,
         command: &str,
-        on_data_cb: impl Fn(CryptoVec) -> F,
-    ) -> eyre::Result<u32> {
+        on_data_cb: impl Fn(OutputStream, CryptoVec) -> F,
+    ) -> eyre::Result<Option<u32>> {
+        self.call_with_stdin(command, None, on_data_cb).await
+    }
+
+    pub async fn call_with_stdin<F: Future<Output = Res>>(
+        &mut self,
+        command: &str,
+        stdin: Option<&[u8]>,
+        on_data_cb: impl Fn(OutputStream, CryptoVec) -> F,
+    ) -> eyre::Result<Option<u32>> {
         let mut channel = self.session.channel_open_session().await?;
         channel.exec(true, command).await?;
 
+        if let Some(stdin) = stdin {
+            channel.data(stdin).await?;
+        }
+        channel.eof().await?;
+
         let mut code = None;
 
         loop {
@@ -119,7 +366,11 @@ impl SshSession {
             };
             match msg {
                 ChannelMsg::Data { data } => {
-                    on_data_cb(data).await?;
+                    on_data_cb(OutputStream::Stdout, data).await?;
+                }
+                // extended data type 1 is stderr (rfc 4254 section 5.2)
+                ChannelMsg::ExtendedData { data, ext: 1 } => {
+                    on_data_cb(OutputStream::Stderr, data).await?;
                 }
                 ChannelMsg::ExitStatus { exit_status } => {
                     code = Some(exit_status);
@@ -127,7 +378,7 @@ impl SshSession {
                 _ => {}
             }
         }
-        Ok(code.expect("program did not exit cleanly"))
+        Ok(code)
     }
 
     pub async fn get_sftp(&self) -> eyre::Result<SftpSession> {
@@ -143,3 +394,10 @@ impl SshSession {
         Ok(())
     }
 }
+
+fn session_addr(host: &str, port: usize) -> eyre::Result<SocketAddr> {
+    Ok(SocketAddr::from((
+        IpAddr::V4(Ipv4Addr::from_str(host)?),
+        port as u16,
+    )))
+}
@@ -1,5 +1,7 @@
+use crate::libs::agent_pki::AgentPki;
 use crate::libs::agent_service::AgentService;
 use crate::libs::app_config::{AppConfig, AppConfigRef};
+use crate::libs::db_driver::DbDriver;
 use crate::libs::shared_state::SharedState;
 use clap::Parser;
 use prelude::Res;
@@ -17,15 +19,27 @@ async fn main() -> Res {
     prelude::init_logger().await?;
 
     let mut config = AppConfig::parse();
+    // only an explicit password resets the admin, `--init` alone generates a random one
+    let reset_admin = config.init && !config.pwd.is_empty();
     config.default().await?;
     
     let config = AppConfigRef::from(config);
     
-    let agent_service = AgentService::new(config.clone()).await;
+    libs::secret_box::init(&config.master_key_path).await?;
+    let db_driver = DbDriver::new(&config.db_path)?;
+    libs::auth::bootstrap_admin(&db_driver, &config.pwd, reset_admin)?;
+    libs::deploy_jobs::recover(&db_driver)?;
+
+    let agent_pki = AgentPki::load_or_create().await?;
+
+    let agent_service =
+        AgentService::new(config.clone(), db_driver.clone(), agent_pki.clone()).await;
     
-    let state = SharedState::new(config.clone(), agent_service).await;
+    let state = SharedState::new(config.clone(), db_driver, agent_service, agent_pki).await;
     
     sub_server_io::run(state.clone())?;
+    libs::metric_retention::run(state.clone());
+    libs::alert_engine::run(state.clone());
     api::run(state.clone()).await?;
 
     Ok(())
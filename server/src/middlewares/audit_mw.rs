@@ -0,0 +1,73 @@
+use crate::libs::api_response::ApiResponse;
+use crate::libs::audit::{self, AuditRecord};
+use crate::libs::shared_state::SharedState;
+use crate::models::user::User;
+use axum::body::Body;
+use axum::extract::{MatchedPath, RawPathParams, Request, State};
+use axum::http::Method;
+use axum::middleware::Next;
+use axum::response::Response;
+use axum::Extension;
+
+/// same as the default body limit of the json extractor
+const MAX_BODY_SIZE: usize = 2 * 1024 * 1024;
+
+/// record every mutating request in the audit log, including the rejected ones.
+///
+/// must run after [`require_authentication`](super::auth_mw::require_authentication) as a route layer
+pub async fn audit(
+    State(state): State<SharedState>,
+    Extension(user): Extension<User>,
+    matched_path: MatchedPath,
+    params: RawPathParams,
+    req: Request,
+    next: Next,
+) -> eyre::Result<Response, ApiResponse> {
+    let route = matched_path.as_str();
+    if !is_mutating(req.method(), route) {
+        return Ok(next.run(req).await);
+    }
+
+    let action = format!("{} {route}", req.method());
+    let path = req.uri().path().to_owned();
+    // the other components use `id` for their own records
+    let server_id = params
+        .iter()
+        .find(|(key, _)| match *key {
+            "id" => route.starts_with("/servers/"),
+            "server_id" => true,
+            _ => false,
+        })
+        .map(|(_, value)| value.to_owned());
+
+    let (parts, body) = req.into_parts();
+    let body = axum::body::to_bytes(body, MAX_BODY_SIZE)
+        .await
+        .map_err(|_| ApiResponse::bad_request("the request body is too large"))?;
+    let audit_params = audit::redacted_params(&body);
+
+    let res = next.run(Request::from_parts(parts, Body::from(body))).await;
+    audit::record(
+        &state.db_driver,
+        AuditRecord {
+            user: &user,
+            action,
+            path,
+            server_id,
+            params: audit_params,
+            status: res.status().as_u16(),
+            success: res.status().is_success(),
+        },
+    );
+    Ok(res)
+}
+
+fn is_mutating(method: &Method, route: &str) -> bool {
+    match route {
+        // deploys the agent
+        "/agents/run/{server_id}" => true,
+        // recorded by the handler once the command is known
+        "/servers/{id}/exec/ws" => false,
+        _ => method != Method::GET,
+    }
+}
@@ -1,13 +1,16 @@
 use crate::libs::api_response::ApiResponse;
+use crate::libs::auth::{self, TokenType};
 use crate::libs::shared_state::SharedState;
 use axum::extract::{Request, State};
 use axum::http::header;
 use axum::middleware::Next;
 use axum::response::Response;
 
+/// validate the access token of the request and make its [`UserClaims`](auth::UserClaims)
+/// and the current [`User`](crate::models::user::User) available to the handlers
 pub async fn require_authentication(
     State(state): State<SharedState>,
-    req: Request,
+    mut req: Request,
     next: Next,
 ) -> eyre::Result<Response, ApiResponse> {
     let token = req
@@ -16,9 +19,17 @@ pub async fn require_authentication(
         .and_then(|value| value.to_str().ok().map(|f| f.replace("Bearer ", "")))
         .ok_or_else(|| ApiResponse::unauthorized("authorization header is required"))?;
 
-    if !token.eq(&state.app_config.pwd) {
-        return Err(ApiResponse::unauthorized("invalid auth key!"));
-    }
+    let claims = auth::decode_token(&state.app_config.jwt_secret, &token, TokenType::Access)
+        .map_err(|_| ApiResponse::unauthorized("invalid or expired token"))?;
 
+    // tokens of deleted users are rejected before they expire
+    let user = state
+        .db_driver
+        .get_user_by_id(&claims.sub)
+        .map_err(|e| ApiResponse::internal(&e.to_string()))?
+        .ok_or(ApiResponse::unauthorized("invalid or expired token"))?;
+
+    req.extensions_mut().insert(claims);
+    req.extensions_mut().insert(user);
     Ok(next.run(req).await)
 }
@@ -1 +1,3 @@
-pub mod auth_mw;
\ No newline at end of file
+pub mod audit_mw;
+pub mod auth_mw;
+pub mod rbac_mw;
\ No newline at end of file
@@ -0,0 +1,110 @@
+use crate::libs::api_response::ApiResponse;
+use crate::libs::shared_state::SharedState;
+use crate::models::user::{Role, User};
+use axum::extract::{MatchedPath, RawPathParams, Request, State};
+use axum::http::Method;
+use axum::middleware::Next;
+use axum::response::Response;
+use axum::Extension;
+
+/// check the role of the user against the route and, for the routes of a single server,
+/// whether the server is in one of the groups of the user.
+///
+/// must run after [`require_authentication`](super::auth_mw::require_authentication) as a route layer,
+/// so the matched path and its params are available
+pub async fn authorize_servers(
+    State(state): State<SharedState>,
+    Extension(user): Extension<User>,
+    matched_path: MatchedPath,
+    params: RawPathParams,
+    req: Request,
+    next: Next,
+) -> eyre::Result<Response, ApiResponse> {
+    let required = server_route_role(req.method(), matched_path.as_str());
+    if user.role < required {
+        return Err(ApiResponse::forbidden(&format!(
+            "this action requires the {required:?} role"
+        )));
+    }
+
+    let server_id = params
+        .iter()
+        .find(|(key, _)| matches!(*key, "id" | "server_id"))
+        .map(|(_, value)| value.to_owned());
+    if let Some(server_id) = server_id.filter(|_| user.is_scoped()) {
+        let server = state
+            .db_driver
+            .get_server_by_id(server_id)
+            .map_err(|e| ApiResponse::internal(&e.to_string()))?;
+        // unknown servers are reported by the handlers
+        if server.is_some_and(|s| !user.can_access(&s)) {
+            return Err(ApiResponse::forbidden(
+                "you don't have access to this server",
+            ));
+        }
+    }
+
+    Ok(next.run(req).await)
+}
+
+/// reads are allowed for everyone, changes require an operator
+pub async fn require_operator_for_writes(
+    Extension(user): Extension<User>,
+    req: Request,
+    next: Next,
+) -> eyre::Result<Response, ApiResponse> {
+    if req.method() != Method::GET && user.role < Role::Operator {
+        return Err(ApiResponse::forbidden(
+            "this action requires the Operator role",
+        ));
+    }
+    Ok(next.run(req).await)
+}
+
+/// the resources that aren't tied to a server (e.g. notification channels) are shared by every group,
+/// so scoped users can only read them
+pub async fn require_unscoped_for_writes(
+    Extension(user): Extension<User>,
+    req: Request,
+    next: Next,
+) -> eyre::Result<Response, ApiResponse> {
+    if req.method() != Method::GET && user.is_scoped() {
+        return Err(ApiResponse::forbidden(
+            "this action is not available to users limited to server groups",
+        ));
+    }
+    Ok(next.run(req).await)
+}
+
+pub async fn require_admin(
+    Extension(user): Extension<User>,
+    req: Request,
+    next: Next,
+) -> eyre::Result<Response, ApiResponse> {
+    if user.role != Role::Admin {
+        return Err(ApiResponse::forbidden(
+            "this action requires the Admin role",
+        ));
+    }
+    Ok(next.run(req).await)
+}
+
+fn server_route_role(method: &Method, path: &str) -> Role {
+    let path = path.trim_end_matches('/');
+    match (method, path) {
+        // adding and removing servers or touching their credentials,
+        // changing the address or the pinned key can redirect the credentials to another host
+        (&Method::POST, "/servers")
+        | (&Method::PUT, "/servers/{id}")
+        | (&Method::DELETE, "/servers/{id}")
+        | (&Method::PUT, "/servers/{id}/host-key")
+        | (&Method::DELETE, "/agents/{server_id}")
+        | (_, "/servers/{id}/secret")
+        | (_, "/agents/{server_id}/credentials/rotate")
+        | (_, "/agents/{server_id}/credentials/revoke") => Role::Admin,
+        // these are GET requests but run commands on the server
+        (_, "/servers/{id}/exec/ws") | (_, "/agents/run/{server_id}") => Role::Operator,
+        (&Method::GET, _) => Role::Viewer,
+        _ => Role::Operator,
+    }
+}
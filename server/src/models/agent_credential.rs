@@ -0,0 +1,37 @@
+use crate::libs::rmp_serializer::RmpSerde;
+use chrono::NaiveDateTime;
+use native_db::ToKey;
+use native_model::{native_model, Model};
+use serde::{Deserialize, Serialize};
+
+/// the token the agent of a server currently authenticates with.
+///
+/// only the token with this id (or the pending one during a rotation) is accepted
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[native_model(id = 9, version = 1, with = RmpSerde)]
+#[native_db::native_db]
+pub struct AgentCredential {
+    #[primary_key]
+    pub server_id: String,
+    pub jti: String,
+    pub issued_at: NaiveDateTime,
+    /// the token that was pushed to the agent but not confirmed yet
+    pub pending_jti: Option<String>,
+    /// the client certificate of the agent, see [`fingerprint`](crate::libs::agent_pki::fingerprint).
+    /// `None` for agents deployed before the channel was encrypted
+    #[serde(default)]
+    pub cert_fingerprint: Option<String>,
+}
+
+/// a token that is no longer accepted, even if its signature is valid
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[native_model(id = 10, version = 1, with = RmpSerde)]
+#[native_db::native_db]
+pub struct RevokedAgentToken {
+    #[primary_key]
+    pub jti: String,
+    pub server_id: String,
+    pub revoked_at: NaiveDateTime,
+    /// e.g. `rotated`, `redeployed`, `revoked`
+    pub reason: String,
+}
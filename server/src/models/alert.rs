@@ -0,0 +1,69 @@
+use crate::libs::rmp_serializer::RmpSerde;
+use chrono::NaiveDateTime;
+use native_db::ToKey;
+use native_model::{native_model, Model};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[native_model(id = 4, version = 1, with = RmpSerde)]
+#[native_db::native_db]
+pub struct AlertRule {
+    #[primary_key]
+    pub id: String,
+    pub name: String,
+    /// `None` applies the rule to every server
+    pub server_id: Option<String>,
+    pub condition: AlertCondition,
+    /// how long the condition has to hold before the alert fires
+    pub for_secs: u64,
+    pub enabled: bool,
+    /// ids of the notification channels that are notified when the alert fires or resolves
+    #[serde(default)]
+    pub channels: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(tag = "type", content = "value")]
+pub enum AlertCondition {
+    /// cpu usage in percent
+    CpuAbove(f64),
+    /// memory usage in percent
+    MemoryAbove(f64),
+    /// free space of the fullest disk in percent
+    DiskFreeBelow(f64),
+    AgentOffline,
+}
+
+/// the state of a rule on a single server
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[native_model(id = 5, version = 1, with = RmpSerde)]
+#[native_db::native_db(primary_key(pk -> String))]
+pub struct Alert {
+    pub rule_id: String,
+    pub server_id: String,
+    pub status: AlertStatus,
+    /// when the condition started to hold
+    pub pending_since: NaiveDateTime,
+    pub fired_at: Option<NaiveDateTime>,
+    pub resolved_at: Option<NaiveDateTime>,
+    /// the last observed value (cpu %, offline seconds, ..)
+    pub value: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertStatus {
+    Pending,
+    Firing,
+    Resolved,
+}
+
+impl Alert {
+    fn pk(&self) -> String {
+        Self::key(&self.rule_id, &self.server_id)
+    }
+
+    pub fn key(rule_id: &str, server_id: &str) -> String {
+        format!("{rule_id}:{server_id}")
+    }
+}
@@ -0,0 +1,39 @@
+use crate::libs::rmp_serializer::RmpSerde;
+use chrono::NaiveDateTime;
+use native_db::ToKey;
+use native_model::{native_model, Model};
+use serde::{Deserialize, Serialize};
+
+/// a mutating request made to the api, entries are never removed.
+///
+/// only `success` of a streamed command is updated once the command finishes
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[native_model(id = 8, version = 1, with = RmpSerde)]
+#[native_db::native_db(primary_key(pk -> String))]
+pub struct AuditEntry {
+    pub id: String,
+    pub time: NaiveDateTime,
+    pub user_id: String,
+    pub username: String,
+    /// the method and the route, e.g. `POST /servers/{id}/exec`
+    pub action: String,
+    /// the requested path, e.g. `/servers/abc/exec`
+    pub path: String,
+    pub server_id: Option<String>,
+    /// the json body of the request with the secrets redacted
+    pub params: Option<String>,
+    /// the http status of the response
+    pub status: u16,
+    pub success: bool,
+}
+
+impl AuditEntry {
+    fn pk(&self) -> String {
+        format!("{}:{}", Self::time_key(&self.time), self.id)
+    }
+
+    /// the entries are keyed by the zero padded unix millis first, so the lexical order is chronological
+    pub fn time_key(time: &NaiveDateTime) -> String {
+        format!("{:020}", time.and_utc().timestamp_millis().max(0))
+    }
+}
@@ -0,0 +1,51 @@
+use crate::libs::rmp_serializer::RmpSerde;
+use chrono::NaiveDateTime;
+use native_db::ToKey;
+use native_model::{native_model, Model};
+use serde::{Deserialize, Serialize};
+
+/// a deployment or removal of the agent on a server over ssh
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[native_model(id = 11, version = 1, with = RmpSerde)]
+#[native_db::native_db]
+pub struct DeployJob {
+    #[primary_key]
+    pub id: String,
+    pub server_id: String,
+    pub status: DeployStatus,
+    /// upload and initialize the agent even if the same build is already running on the server
+    pub force: bool,
+    pub created_at: NaiveDateTime,
+    pub finished_at: Option<NaiveDateTime>,
+    pub logs: Vec<DeployLog>,
+    /// set when the job failed
+    pub error: Option<String>,
+    /// the records are encoded positionally, new fields go last
+    #[serde(default)]
+    pub kind: JobKind,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum JobKind {
+    #[default]
+    Deploy,
+    Uninstall,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DeployStatus {
+    Queued,
+    Uploading,
+    Initializing,
+    Succeeded,
+    Failed,
+    Uninstalling,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DeployLog {
+    pub time: NaiveDateTime,
+    pub message: String,
+}
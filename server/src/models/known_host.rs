@@ -0,0 +1,18 @@
+use crate::libs::rmp_serializer::RmpSerde;
+use chrono::NaiveDateTime;
+use native_db::ToKey;
+use native_model::{native_model, Model};
+use serde::{Deserialize, Serialize};
+
+/// the ssh host key that was pinned for a server on the first connection (trust on first use)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[native_model(id = 3, version = 1, with = RmpSerde)]
+#[native_db::native_db]
+pub struct KnownHost {
+    #[primary_key]
+    pub server_id: String,
+    /// sha256 fingerprint, e.g. `SHA256:uNiVztksCsDhcc0u9e8BujQXVUpKZIDTMczCvj3tD2s`
+    pub fingerprint: String,
+    pub algorithm: String,
+    pub accepted_at: NaiveDateTime,
+}
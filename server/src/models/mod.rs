@@ -1,2 +1,9 @@
+pub mod agent_credential;
+pub mod alert;
+pub mod audit_entry;
+pub mod deploy_job;
+pub mod known_host;
+pub mod notification_channel;
 pub mod server;
-pub mod server_metric;
\ No newline at end of file
+pub mod server_metric;
+pub mod user;
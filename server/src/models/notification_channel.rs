@@ -0,0 +1,64 @@
+use crate::libs::rmp_serializer::RmpSerde;
+use crate::libs::secret_box::SealedSecret;
+use native_db::ToKey;
+use native_model::{native_model, Model};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[native_model(id = 6, version = 1, with = RmpSerde)]
+#[native_db::native_db]
+pub struct NotificationChannel {
+    #[primary_key]
+    pub id: String,
+    pub name: String,
+    pub kind: ChannelKind,
+    /// the smtp password, sealed with the master key
+    pub secret: Option<SealedSecret>,
+    /// notify when an agent disconnects, without having to define an alert rule
+    pub agent_events: bool,
+    pub enabled: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", content = "value")]
+pub enum ChannelKind {
+    /// POSTs the notification as json
+    Webhook {
+        url: String,
+        #[serde(default)]
+        headers: HashMap<String, String>,
+    },
+    Email {
+        host: String,
+        port: u16,
+        #[serde(default)]
+        username: Option<String>,
+        #[serde(default)]
+        tls: SmtpTls,
+        from: String,
+        to: Vec<String>,
+    },
+    /// a request with a templated body, e.g. for chat tools.
+    ///
+    /// the `{{title}}`, `{{message}}`, `{{status}}`, `{{server}}`, `{{value}}` and `{{time}}` placeholders
+    /// are replaced json escaped (e.g. `"` -> `\"`), `{{raw.title}}` etc. insert the value verbatim.
+    /// `{{json}}` is replaced with the whole notification as json
+    Http {
+        method: String,
+        url: String,
+        #[serde(default)]
+        headers: HashMap<String, String>,
+        body_template: String,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SmtpTls {
+    /// plaintext, only meant for local relays and test servers
+    None,
+    #[default]
+    StartTls,
+    Tls,
+}
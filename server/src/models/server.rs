@@ -1,12 +1,14 @@
 use crate::api::components::servers::models::AddOrUpdateServerRequest;
 
 use crate::libs::rmp_serializer::RmpSerde;
+use crate::libs::secret_box::SealedSecret;
+use chrono::NaiveDateTime;
 use native_db::ToKey;
 use native_model::{native_model, Model};
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug, Default)]
-#[native_model(id = 1, version = 1,with = RmpSerde)]
+#[native_model(id = 1, version = 2, with = RmpSerde, try_from = (ServerV1, eyre::Report))]
 #[native_db::native_db]
 pub struct Server {
     #[primary_key]
@@ -17,26 +19,164 @@ pub struct Server {
     /// default 20
     pub port: usize,
     pub user: String,
+    /// sealed with the master key, see [`SealedSecret::open`]
+    pub secret: SealedSecret,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// the last time a message was received from the agent of this server
+    #[serde(default)]
+    pub last_seen: Option<NaiveDateTime>,
+    /// `uname -m` of the server, detected when the agent is deployed
+    #[serde(default)]
+    pub arch: Option<String>,
+    /// how the deployment of the agent gets root when `user` isn't root
+    #[serde(default)]
+    pub escalation: Escalation,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Escalation {
+    /// `user` is root, the commands run as they are
+    #[default]
+    None,
+    /// `sudo` with the password of the secret, see [`ServerSecret::sudo_password`]
+    Sudo,
+    /// `sudo -n`, the user has a NOPASSWD rule
+    SudoNopasswd,
+}
+
+/// the first version of [`Server`] which stored the secret in plaintext
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[native_model(id = 1, version = 1, with = RmpSerde)]
+#[native_db::native_db]
+pub struct ServerV1 {
+    #[primary_key]
+    pub id: String,
+    pub name: String,
+    #[secondary_key(unique)]
+    pub ip: String,
+    pub port: usize,
+    pub user: String,
     pub secret: ServerSecret,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
-#[serde(tag = "type", content = "value")]
+#[serde(tag = "type", content = "value", from = "ServerSecretRepr")]
 #[native_model(id = 1, version = 1,with = RmpSerde)]
 pub enum ServerSecret {
     Pwd(String),
-    SshKey(String),
+    /// private key in OpenSSH or PEM format (ed25519, rsa, ecdsa)
+    SshKey {
+        key: String,
+        #[serde(default)]
+        passphrase: Option<String>,
+        /// only needed with [`Escalation::Sudo`]
+        #[serde(default)]
+        sudo_password: Option<String>,
+    },
 }
 
 impl Server {
-    pub fn from_req(id: String, value: AddOrUpdateServerRequest) -> Self {
+    /// apply the request to the current record, the secret and the agent state are kept
+    pub fn from_req(current: Server, value: AddOrUpdateServerRequest) -> Self {
         Self {
-            id,
             user: value.user,
             name: value.name,
             ip: value.ip,
             port: value.port.unwrap_or(20),
-            secret: value.secret,
+            tags: value.tags,
+            escalation: value.escalation,
+            ..current
+        }
+    }
+}
+
+impl TryFrom<ServerV1> for Server {
+    type Error = eyre::Report;
+
+    fn try_from(value: ServerV1) -> Result<Self, Self::Error> {
+        Ok(Self {
+            // the master key is initialized before the db is opened, so this can only fail on a broken setup
+            secret: SealedSecret::seal(&value.secret)?,
+            id: value.id,
+            name: value.name,
+            ip: value.ip,
+            port: value.port,
+            user: value.user,
+            tags: vec![],
+            last_seen: None,
+            arch: None,
+            escalation: Escalation::None,
+        })
+    }
+}
+
+/// a secret that can't be opened fails the downgrade instead of being replaced with an empty one
+impl TryFrom<Server> for ServerV1 {
+    type Error = eyre::Report;
+
+    fn try_from(value: Server) -> Result<Self, Self::Error> {
+        Ok(Self {
+            secret: value.secret.open::<ServerSecret>()?,
+            id: value.id,
+            name: value.name,
+            ip: value.ip,
+            port: value.port,
+            user: value.user,
+        })
+    }
+}
+
+/// the decoding side of [`ServerSecret`], `SshKey` used to be just the key
+#[derive(Deserialize)]
+#[serde(tag = "type", content = "value")]
+enum ServerSecretRepr {
+    Pwd(String),
+    SshKey(SshKeyRepr),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SshKeyRepr {
+    Legacy(String),
+    Key {
+        key: String,
+        #[serde(default)]
+        passphrase: Option<String>,
+        #[serde(default)]
+        sudo_password: Option<String>,
+    },
+}
+
+impl From<ServerSecretRepr> for ServerSecret {
+    fn from(value: ServerSecretRepr) -> Self {
+        match value {
+            ServerSecretRepr::Pwd(pwd) => ServerSecret::Pwd(pwd),
+            ServerSecretRepr::SshKey(SshKeyRepr::Legacy(key)) => ServerSecret::SshKey {
+                key,
+                passphrase: None,
+                sudo_password: None,
+            },
+            ServerSecretRepr::SshKey(SshKeyRepr::Key {
+                key,
+                passphrase,
+                sudo_password,
+            }) => ServerSecret::SshKey {
+                key,
+                passphrase,
+                sudo_password,
+            },
+        }
+    }
+}
+
+impl ServerSecret {
+    /// the login password doubles as the sudo password
+    pub fn sudo_password(&self) -> Option<&str> {
+        match self {
+            ServerSecret::Pwd(pwd) => Some(pwd),
+            ServerSecret::SshKey { sudo_password, .. } => sudo_password.as_deref(),
         }
     }
 }
@@ -4,13 +4,31 @@ use machine_info::{SystemInfo, SystemStatus};
 use native_db::ToKey;
 use native_model::{native_model, Model};
 use serde::{Deserialize, Serialize};
+
+/// a single sample reported by an agent.
+///
+/// samples are append-only and keyed by `(server_id, time)`, see [`ServerMetric::key`]
 #[derive(Serialize, Deserialize)]
-#[native_model(id = 2, version = 1, with = RmpSerde)]
-#[native_db::native_db]
+#[native_model(id = 2, version = 2, with = RmpSerde)]
+#[native_db::native_db(primary_key(pk -> String))]
 pub struct ServerMetric {
-    #[primary_key]
     pub server_id: String,
     pub time: NaiveDateTime,
     pub system_info: SystemInfo,
     pub system_status: Option<SystemStatus>,
 }
+
+impl ServerMetric {
+    fn pk(&self) -> String {
+        Self::key(&self.server_id, &self.time)
+    }
+
+    /// `{server_id}:{unix millis}`, the timestamp is zero padded so the lexical order of the keys
+    /// matches the chronological order of the samples within a server
+    pub fn key(server_id: &str, time: &NaiveDateTime) -> String {
+        format!(
+            "{server_id}:{:020}",
+            time.and_utc().timestamp_millis().max(0)
+        )
+    }
+}
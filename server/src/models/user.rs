@@ -0,0 +1,55 @@
+use crate::libs::rmp_serializer::RmpSerde;
+use crate::models::server::Server;
+use chrono::NaiveDateTime;
+use native_db::ToKey;
+use native_model::{native_model, Model};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[native_model(id = 7, version = 1, with = RmpSerde)]
+#[native_db::native_db]
+pub struct User {
+    #[primary_key]
+    pub id: String,
+    #[secondary_key(unique)]
+    pub username: String,
+    /// argon2 hash in the PHC string format
+    pub password_hash: String,
+    pub created_at: NaiveDateTime,
+    /// users created before the roles existed had full access
+    #[serde(default = "Role::admin")]
+    pub role: Role,
+    /// the user can only access the servers that have at least one of these tags,
+    /// empty for every server. ignored for admins
+    #[serde(default)]
+    pub server_groups: Vec<String>,
+}
+
+/// ordered by privilege, every role can do what the previous ones can
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// read the servers, metrics, alerts and services
+    Viewer,
+    /// run commands, manage services and deploy agents
+    Operator,
+    /// manage the users, the servers and their credentials
+    Admin,
+}
+
+impl Role {
+    fn admin() -> Self {
+        Self::Admin
+    }
+}
+
+impl User {
+    /// whether the user is limited to a subset of the servers
+    pub fn is_scoped(&self) -> bool {
+        self.role != Role::Admin && !self.server_groups.is_empty()
+    }
+
+    pub fn can_access(&self, server: &Server) -> bool {
+        !self.is_scoped() || server.tags.iter().any(|t| self.server_groups.contains(t))
+    }
+}
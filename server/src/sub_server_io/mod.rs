@@ -1,67 +1,122 @@
+use crate::libs::notifier::Notification;
 use crate::libs::shared_state::SharedState;
-use crate::libs::TokenClaims;
+use crate::libs::{agent_credentials, alert_engine};
 use crate::models::server_metric::ServerMetric;
 use crate::prelude::Res;
-use agent_shared::{ClientMessage, ClientMessageDetail, ServerMessage, Signal};
+use crate::sub_server_io::tunnel::TunnelPeers;
+use agent_shared::{
+    AgentResponse, ClientMessage, ClientMessageDetail, Envelope, Frame, ServerMessage, Signal,
+    PROTOCOL_VERSION,
+};
 use chrono::Utc;
-use jsonwebtoken::{decode, DecodingKey, Validation};
 use message_io::network::{Endpoint, NetEvent, Transport};
 use message_io::node;
 use message_io::node::{NodeEvent, NodeHandler};
+use std::collections::HashMap;
 use std::net::ToSocketAddrs;
-use std::ops::Deref;
-use std::sync::LazyLock;
+use std::time::{Duration, Instant};
 
-static V: LazyLock<Validation> = LazyLock::new(|| {
-    let mut v = Validation::default();
-    v.validate_exp = false;
-    v
-});
+mod tunnel;
+
+/// how long a new connection has to send its [`ClientMessageDetail::Hello`]
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
 
 pub fn run(state: SharedState) -> Res {
-    let secret = state.app_config.pwd.as_bytes().to_owned();
-    std::thread::spawn(|| {
-        log::info!("creating sub-server io");
-        let (handler, listener) = node::split::<Signal>();
-        let addr = ("0.0.0.0", 3939).to_socket_addrs()?.next().unwrap();
-        match handler.network().listen(Transport::FramedTcp, addr) {
-            Ok((id, real_addr)) => println!("sub server listener({id}) running at {}", real_addr),
-            Err(_) => {
-                println!("Can not listening at {}", addr);
-                return Ok(());
-            }
+    log::info!("creating sub-server io");
+    let (handler, listener) = node::split::<Signal>();
+    // only reachable through the tls tunnel, which listens for the agents
+    let addr = ("127.0.0.1", 0).to_socket_addrs()?.next().unwrap();
+    let internal_addr = match handler.network().listen(Transport::FramedTcp, addr) {
+        Ok((id, real_addr)) => {
+            println!("sub server listener({id}) running at {}", real_addr);
+            state.agent_hub.attach(handler.clone());
+            real_addr
         }
+        Err(_) => {
+            println!("Can not listening at {}", addr);
+            return Ok(());
+        }
+    };
+    let peers = TunnelPeers::default();
+    tunnel::run(state.clone(), internal_addr, peers.clone())?;
+
+    handler
+        .signals()
+        .send_with_timer(Signal::SweepHandshakes, HANDSHAKE_TIMEOUT);
 
+    std::thread::spawn(move || {
+        // connections that did not complete the handshake yet
+        let mut pending = HashMap::<Endpoint, Instant>::new();
         listener.for_each(move |event| match event {
             NodeEvent::Network(nw) => match nw {
                 NetEvent::Connected(endpoint, _) => {
                     println!("Client ({}) connected", endpoint.addr());
                 }
+                NetEvent::Accepted(endpoint, _) => {
+                    pending.insert(endpoint, Instant::now());
+                }
                 NetEvent::Message(endpoint, input_data) => {
-                    let Ok(message) = bincode::deserialize::<ClientMessage>(input_data) else {
-                        log::info!(
-                            "received unknown data from {endpoint}: {:?}",
-                            String::from_utf8_lossy(input_data)
-                        );
-                        return;
+                    let frame = agent_shared::decode::<ClientMessage>(input_data);
+                    let (version, message) = match frame {
+                        Ok(Frame::Message { version, message }) => (version, message),
+                        Ok(Frame::Legacy(message)) => (1, message),
+                        Ok(Frame::Unknown(envelope)) => {
+                            skip_unknown(&state, endpoint, envelope);
+                            return;
+                        }
+                        Err(_) => {
+                            log::info!(
+                                "received unknown data from {endpoint}: {:?}",
+                                String::from_utf8_lossy(input_data)
+                            );
+                            return;
+                        }
                     };
                     log::info!("{message:?}");
-                    let Some(claims) = authenticate_client(&secret, &message.token) else {
-                        log::info!("removing unauthorized access: {}", endpoint.addr());
+                    let Some(server_id) = authenticate(&state, &peers, &message, endpoint) else {
+                        let remote = peers.get(&endpoint.addr()).map(|p| p.remote);
+                        log::info!("removing unauthorized access: {remote:?}");
                         handler.network().remove(endpoint.resource_id());
+                        pending.remove(&endpoint);
                         return;
                     };
-                    let result =
-                        process_message(state.clone(), &handler, message, endpoint, claims);
+                    pending.remove(&endpoint);
+                    let result = process_message(
+                        state.clone(),
+                        &handler,
+                        message,
+                        endpoint,
+                        server_id,
+                        version,
+                    );
                     if let Err(e) = result {
                         log::error!("{e:?}");
                     }
                 }
                 NetEvent::Disconnected(endpoint) => {
                     println!("Client ({}) disconnected", endpoint.addr());
+                    pending.remove(&endpoint);
+                    if let Some(server_id) = state.agent_hub.unbind(endpoint) {
+                        if let Err(e) = on_agent_disconnected(&state, server_id) {
+                            log::error!("{e:?}");
+                        }
+                    }
                 }
                 _ => {}
             },
+            NodeEvent::Signal(Signal::SweepHandshakes) => {
+                pending.retain(|endpoint, since| {
+                    let expired = since.elapsed() >= HANDSHAKE_TIMEOUT;
+                    if expired {
+                        log::info!("no handshake from {} in time, dropping it", endpoint.addr());
+                        handler.network().remove(endpoint.resource_id());
+                    }
+                    !expired
+                });
+                handler
+                    .signals()
+                    .send_with_timer(Signal::SweepHandshakes, HANDSHAKE_TIMEOUT);
+            }
             NodeEvent::Signal(signal) => {
                 log::info!("received signal {signal:?}");
             }
@@ -72,46 +127,143 @@ pub fn run(state: SharedState) -> Res {
     Ok(())
 }
 
+/// the server the message comes from.
+///
+/// the token is only checked in the handshake, later messages are trusted by their endpoint.
+/// agents that predate the handshake send their token with every message
+fn authenticate(
+    state: &SharedState,
+    peers: &TunnelPeers,
+    message: &ClientMessage,
+    endpoint: Endpoint,
+) -> Option<String> {
+    let token = match &message.message {
+        ClientMessageDetail::Hello { token, .. } => Some(token.clone()),
+        _ => match state.agent_hub.server_of(&endpoint) {
+            Some(server_id) => return Some(server_id),
+            None => message.token.clone(),
+        },
+    };
+    agent_credentials::authenticate(state, &token)
+        .filter(|c| peers.allows(&endpoint.addr(), &c.sub))
+        .map(|c| c.sub)
+}
+
 fn process_message(
     state: SharedState,
     handler: &NodeHandler<Signal>,
     message: ClientMessage,
     endpoint: Endpoint,
-    claims: TokenClaims,
+    server_id: String,
+    version: u16,
 ) -> Res {
     log::info!(
-        "msg: (from {}), (token {}), (msg {message:?})",
-        endpoint.addr(),
-        claims.sub
+        "msg: (from {}), (server {server_id}, protocol v{version}), (msg {message:?})",
+        endpoint.addr()
     );
+    if state.agent_hub.bind(&server_id, endpoint, version) {
+        state
+            .db_driver
+            .set_last_seen(&server_id, Utc::now().naive_utc())?;
+    }
     match message.message {
         ClientMessageDetail::Ping => {
-            let o = bincode::serialize(&ServerMessage::Ping)?;
+            let o = agent_shared::encode_for(&ServerMessage::Ping, version)?;
             handler.network().send(endpoint, &o);
         }
+        ClientMessageDetail::AgentInfo { version } => {
+            log::info!("agent of {server_id} is running version {version}");
+            state.agent_hub.set_agent_info(&server_id, version, vec![]);
+        }
+        ClientMessageDetail::Hello {
+            agent_version,
+            capabilities,
+            ..
+        } => {
+            log::info!(
+                "agent of {server_id} is running version {agent_version} ({})",
+                capabilities.join(", ")
+            );
+            let features = state
+                .agent_hub
+                .set_agent_info(&server_id, agent_version, capabilities);
+            // the agents that predate the envelope can't decode the welcome
+            if version >= 2 {
+                state.agent_hub.send(
+                    &server_id,
+                    &ServerMessage::Welcome {
+                        protocol_version: PROTOCOL_VERSION,
+                        features,
+                    },
+                )?;
+            }
+        }
         ClientMessageDetail::UpdateMetric { metric } => {
             let now = Utc::now().naive_utc();
             log::info!("received metrics from {} in [{} UTC]", endpoint.addr(), now);
-            state.db_driver.add_metric(ServerMetric {
+            let metric = ServerMetric {
                 system_status: metric.system_status,
                 system_info: metric.system_info,
-                server_id: claims.sub,
+                server_id,
                 time: now,
-            })?;
+            };
+            if let Err(e) = alert_engine::on_metric(&state, &metric) {
+                log::error!("failed to evaluate the alert rules: {e:?}");
+            }
+            state.db_driver.add_metric(metric)?;
+        }
+        ClientMessageDetail::Response { id, response } => {
+            state
+                .agent_hub
+                .complete_from(&server_id, endpoint, id, response);
         }
     }
     Ok(())
 }
 
-fn authenticate_client(secret: &[u8], token: &Option<String>) -> Option<TokenClaims> {
-    Some(
-        decode::<TokenClaims>(
-            token.as_ref()?,
-            &DecodingKey::from_secret(secret),
-            V.deref(),
-        )
-        .inspect_err(|e| log::error!("{e:?}"))
-        .ok()?
-        .claims,
-    )
+/// a message of a newer agent, a response still completes its request so the caller doesn't hang
+fn skip_unknown(state: &SharedState, endpoint: Endpoint, envelope: Envelope) {
+    let Some(server_id) = state.agent_hub.server_of(&endpoint) else {
+        log::info!(
+            "ignoring `{}` from {endpoint} before its handshake",
+            envelope.kind
+        );
+        return;
+    };
+    log::info!(
+        "skipping the unsupported message `{}` (protocol v{}) of {server_id}",
+        envelope.kind,
+        envelope.version
+    );
+    if let Some(id) = envelope.request_id {
+        let message = format!(
+            "the server can't decode the `{}` response of the agent, update the server",
+            envelope.kind
+        );
+        // the envelope is only trusted with the requests that were sent to this agent
+        state
+            .agent_hub
+            .complete_from(&server_id, endpoint, id, AgentResponse::Error { message });
+    }
+}
+
+fn on_agent_disconnected(state: &SharedState, server_id: String) -> Res {
+    let now = Utc::now().naive_utc();
+    state.db_driver.set_last_seen(&server_id, now)?;
+
+    let server_name = state
+        .db_driver
+        .get_server_by_id(server_id.clone())?
+        .map(|s| s.name);
+    let name = server_name.as_deref().unwrap_or(&server_id);
+    state.notifier.notify_agent_event(Notification {
+        title: format!("[offline] {name}"),
+        message: format!("the agent of {name} disconnected"),
+        status: "offline".into(),
+        server_id: Some(server_id.clone()),
+        server_name: server_name.clone(),
+        value: None,
+        time: now,
+    });
+    Ok(())
 }
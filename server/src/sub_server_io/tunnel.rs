@@ -0,0 +1,141 @@
+use crate::libs::agent_pki;
+use crate::libs::shared_state::SharedState;
+use crate::prelude::Res;
+use eyre::eyre;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::TlsAcceptor;
+
+pub const AGENT_PORT: u16 = 3939;
+/// the first byte of a tls record that carries a handshake. a message-io frame starts with its varint length,
+/// which would be 22 bytes here, and no agent message (they all carry a jwt) is that short
+const TLS_HANDSHAKE: u8 = 0x16;
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// the connections the tunnel forwards to the message-io listener,
+/// keyed by the local address of the forwarded connection (the address message-io sees)
+#[derive(Clone, Default)]
+pub struct TunnelPeers {
+    inner: Arc<Mutex<HashMap<SocketAddr, TunnelPeer>>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct TunnelPeer {
+    pub remote: SocketAddr,
+    /// the server the client certificate was issued to, `None` for plaintext agents
+    pub server_id: Option<String>,
+}
+
+impl TunnelPeers {
+    pub fn get(&self, addr: &SocketAddr) -> Option<TunnelPeer> {
+        self.inner.lock().unwrap().get(addr).cloned()
+    }
+
+    /// whether the agent on the connection may act as the server, the token has to match the certificate
+    pub fn allows(&self, addr: &SocketAddr, server_id: &str) -> bool {
+        self.get(addr)
+            .is_some_and(|p| p.server_id.as_deref().is_none_or(|id| id.eq(server_id)))
+    }
+
+    fn insert(&self, addr: SocketAddr, peer: TunnelPeer) {
+        self.inner.lock().unwrap().insert(addr, peer);
+    }
+
+    fn remove(&self, addr: &SocketAddr) {
+        self.inner.lock().unwrap().remove(addr);
+    }
+}
+
+/// message-io has no tls support, so the agents connect to this listener
+/// which terminates the (mutual) tls and forwards the plain frames to message-io on `internal`
+pub fn run(state: SharedState, internal: SocketAddr, peers: TunnelPeers) -> Res {
+    let acceptor = TlsAcceptor::from(Arc::new(state.agent_pki.server_config()?));
+    tokio::spawn(async move {
+        let listener = match TcpListener::bind(("0.0.0.0", AGENT_PORT)).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                log::error!("can not listen at {AGENT_PORT}: {e}");
+                return;
+            }
+        };
+        log::info!("agent listener running at 0.0.0.0:{AGENT_PORT}");
+        loop {
+            let Ok((stream, remote)) = listener.accept().await else {
+                continue;
+            };
+            let (state, acceptor, peers) = (state.clone(), acceptor.clone(), peers.clone());
+            tokio::spawn(async move {
+                if let Err(e) = handle(state, acceptor, peers, stream, remote, internal).await {
+                    log::info!("agent connection of {remote} closed: {e}");
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+async fn handle(
+    state: SharedState,
+    acceptor: TlsAcceptor,
+    peers: TunnelPeers,
+    stream: TcpStream,
+    remote: SocketAddr,
+    internal: SocketAddr,
+) -> Res {
+    let mut first = [0u8; 1];
+    if tokio::time::timeout(HANDSHAKE_TIMEOUT, stream.peek(&mut first)).await?? == 0 {
+        return Ok(());
+    }
+
+    if first[0] == TLS_HANDSHAKE {
+        let tls = tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await??;
+        let cert = tls
+            .get_ref()
+            .1
+            .peer_certificates()
+            .and_then(|certs| certs.first())
+            .ok_or(eyre!("{remote} did not present a client certificate"))?;
+        // the certificate is signed by our ca, but it could belong to a revoked or redeployed agent
+        let credential = state
+            .db_driver
+            .find_agent_credential_by_cert(&agent_pki::fingerprint(cert))?
+            .ok_or(eyre!("the certificate of {remote} is unknown or revoked"))?;
+        let peer = TunnelPeer {
+            remote,
+            server_id: Some(credential.server_id),
+        };
+        return forward(tls, peer, internal, &peers).await;
+    }
+
+    if !state.app_config.allow_plaintext_agents {
+        return Err(eyre!(
+            "plaintext connections are not allowed, deploy the agent again or start the server with --allow-plaintext-agents"
+        ));
+    }
+    log::warn!("accepting a plaintext agent connection from {remote}");
+    let peer = TunnelPeer {
+        remote,
+        server_id: None,
+    };
+    forward(stream, peer, internal, &peers).await
+}
+
+async fn forward<S: AsyncRead + AsyncWrite + Unpin>(
+    mut agent: S,
+    peer: TunnelPeer,
+    internal: SocketAddr,
+    peers: &TunnelPeers,
+) -> Res {
+    let mut upstream = TcpStream::connect(internal).await?;
+    let local = upstream.local_addr()?;
+    // registered before any frame is forwarded, so message-io never sees an unknown connection
+    peers.insert(local, peer);
+    let result = tokio::io::copy_bidirectional(&mut agent, &mut upstream).await;
+    peers.remove(&local);
+    result?;
+    Ok(())
+}